@@ -9,6 +9,7 @@ import { createEcosystem } from "./operations/createEcosystem.js";
 import { createTokenAccount } from "./operations/createTokenAccount.js";
 import { depositEcosystem } from "./operations/depositEcosystem.js";
 import { addApprover, removeApprover } from "./operations/manageApprovers.js";
+import { registerMerchant } from "./operations/registerMerchant.js";
 import { createWithdrawalRequest } from "./operations/withdrawalRequest.js";
 import { approveWithdrawalRequest } from "./operations/approveWithdrawal.js";
 import { checkMerchantBalance } from "./operations/debugAccounts.js";
@@ -19,6 +20,7 @@ import {
   DepositConfig,
   ApproverConfig,
   ApprovalConfig,
+  MerchantConfig,
   WithdrawalConfig,
 } from "./types/index.js";
 
@@ -38,6 +40,7 @@ const argv = minimist(process.argv.slice(2), {
     "max-minting-cap",
     "withdrawal-fee-basis-points",
     "deposit-fee-basis-points",
+    "redemption-fee-basis-points",
     "collateral-token-mint",
     "ecosystem-mint",
     "user-collateral-account",
@@ -45,6 +48,11 @@ const argv = minimist(process.argv.slice(2), {
     "approver-address",
     "merchant-wallet",
     "merchant-token-account",
+    "request-index",
+    "merchant-name",
+    "merchant-category",
+    "payout-address",
+    "settlement-mint",
   ],
   boolean: ["verbose", "help"],
   default: {
@@ -58,6 +66,7 @@ const argv = minimist(process.argv.slice(2), {
     "max-minting-cap": "1000000000000",
     "withdrawal-fee-basis-points": "0",
     "deposit-fee-basis-points": "0",
+    "redemption-fee-basis-points": "0",
     amount: "1000000",
     verbose: false,
   },
@@ -100,6 +109,10 @@ async function main(): Promise<void> {
         await handleRemoveApprover(connection, payer);
         break;
 
+      case "register-merchant":
+        await handleRegisterMerchant(connection, payer);
+        break;
+
       case "request-withdrawal":
         await handleCreateWithdrawalRequest(connection, payer);
         break;
@@ -151,6 +164,8 @@ async function handleCreateEcosystem(
     withdrawalFeeBasisPoints: argv["withdrawal-fee-basis-points"],
     depositFeeBasisPoints: argv["deposit-fee-basis-points"],
     collateralTokenMint: argv["collateral-token-mint"],
+    redemptionFeeBasisPoints: argv["redemption-fee-basis-points"],
+    settlementMint: argv["settlement-mint"],
   };
 
   await createEcosystem(connection, payer, config);
@@ -212,6 +227,31 @@ async function handleRemoveApprover(
   await removeApprover(connection, payer, config);
 }
 
+async function handleRegisterMerchant(
+  connection: any,
+  payer: any
+): Promise<void> {
+  if (!argv["ecosystem-mint"]) {
+    throw new Error("--ecosystem-mint is required");
+  }
+  if (!argv["merchant-wallet"]) {
+    throw new Error("--merchant-wallet is required");
+  }
+  if (!argv["merchant-name"]) {
+    throw new Error("--merchant-name is required");
+  }
+
+  const config: MerchantConfig = {
+    ecosystemMint: argv["ecosystem-mint"],
+    merchantWallet: argv["merchant-wallet"],
+    name: argv["merchant-name"],
+    category: argv["merchant-category"] || "",
+    payoutAddress: argv["payout-address"] || undefined,
+  };
+
+  await registerMerchant(connection, payer, config);
+}
+
 async function handleCreateWithdrawalRequest(
   connection: any,
   payer: any
@@ -223,6 +263,7 @@ async function handleCreateWithdrawalRequest(
   const config: WithdrawalConfig = {
     ecosystemMint: argv["ecosystem-mint"],
     merchantWallet: argv["merchant-wallet"] || undefined,
+    amount: argv.amount,
   };
 
   await createWithdrawalRequest(connection, payer, config);
@@ -241,11 +282,16 @@ async function handleApproveWithdrawal(
   if (!argv["merchant-token-account"]) {
     throw new Error("--merchant-token-account is required");
   }
+  if (!argv["request-index"]) {
+    throw new Error("--request-index is required");
+  }
 
   const config: ApprovalConfig = {
     ecosystemMint: argv["ecosystem-mint"],
     merchantWallet: argv["merchant-wallet"],
     merchantTokenAccount: argv["merchant-token-account"],
+    requestIndex: argv["request-index"],
+    settlementMint: argv["settlement-mint"],
   };
 
   await approveWithdrawalRequest(connection, payer, config);
@@ -285,6 +331,8 @@ async function runAllSteps(connection: any, payer: any): Promise<void> {
     withdrawalFeeBasisPoints: argv["withdrawal-fee-basis-points"],
     depositFeeBasisPoints: argv["deposit-fee-basis-points"],
     collateralTokenMint: argv["collateral-token-mint"],
+    redemptionFeeBasisPoints: argv["redemption-fee-basis-points"],
+    settlementMint: argv["settlement-mint"],
   };
 
   const { mint } = await createEcosystem(connection, payer, ecosystemConfig);
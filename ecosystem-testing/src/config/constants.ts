@@ -12,13 +12,17 @@ export const PROGRAM_CONFIG: ProgramConfig = {
   TRANSFER_HOOK: "6BGyrUsGSJiscv8M3hC7JWMm4JKLBXMu3Js4ZQvcNY3G",
 };
 
+export const USDC_MINT = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
 export const SEEDS: Seeds = {
   CONFIG: "config",
   MINT_AUTHORITY: "mint_authority",
   ECOSYSTEM_CONFIG: "ecosystem_config",
   FEE_VAULT_AUTHORITY: "fee_vault_authority",
   FEE_VAULT: "fee_vault",
+  SETTLEMENT_FEE_VAULT: "settlement_fee_vault",
   COLLATERAL_VAULT: "collateral_vault",
+  MERCHANT: "merchant",
 };
 
 export const INSTRUCTION_DISCRIMINATORS: InstructionDiscriminators = {
@@ -29,6 +33,7 @@ export const INSTRUCTION_DISCRIMINATORS: InstructionDiscriminators = {
   REMOVE_APPROVER: [214, 72, 133, 48, 50, 58, 227, 224],
   CREATE_WITHDRAWAL_REQUEST: [37, 98, 178, 192, 168, 139, 43, 242],
   APPROVE_WITHDRAWAL_REQUEST: [190, 168, 219, 52, 136, 10, 126, 172],
+  REGISTER_MERCHANT: [238, 245, 77, 132, 161, 88, 216, 248],
 };
 
 export const DEFAULT_CONFIG: DefaultConfig = {
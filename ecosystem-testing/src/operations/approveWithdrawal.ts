@@ -1,4 +1,10 @@
-import { Connection, Keypair, PublicKey, Transaction } from "@solana/web3.js";
+import {
+  Connection,
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+} from "@solana/web3.js";
 import {
   getAssociatedTokenAddress,
   ASSOCIATED_TOKEN_PROGRAM_ID,
@@ -6,11 +12,14 @@ import {
 import {
   PROGRAM_CONFIG,
   INSTRUCTION_DISCRIMINATORS,
+  USDC_MINT,
 } from "../config/constants.js";
 import {
   findConfigPda,
   findEcosystemConfigPda,
-  findFeeVaultPda,
+  findFeeVaultAuthorityPda,
+  findSettlementFeeVaultPda,
+  findMerchantPda,
 } from "../utils/pda.js";
 import { sendAndConfirmTransactionWithRetry } from "../utils/helpers.js";
 import { getTokenProgram } from "../utils/connection.js";
@@ -22,7 +31,6 @@ import {
 
 // TODO - Move to constants later after verifying if it works correctly
 const VAULT_SEED = "vault";
-const USDC_MINT = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
 
 export async function approveWithdrawalRequest(
   connection: Connection,
@@ -35,17 +43,23 @@ export async function approveWithdrawalRequest(
   const ecosystemMint = new PublicKey(config.ecosystemMint);
   const merchantWallet = new PublicKey(config.merchantWallet);
   const merchantTokenAccount = new PublicKey(config.merchantTokenAccount);
-  const outputMint = new PublicKey(USDC_MINT);
+  const outputMint = new PublicKey(config.settlementMint || USDC_MINT);
 
   const [configPda] = findConfigPda();
   const [ecosystemConfigPda] = findEcosystemConfigPda(ecosystemMint);
-  const [feeVaultPda] = findFeeVaultPda(ecosystemMint);
+  const [feeVaultAuthorityPda] = findFeeVaultAuthorityPda(ecosystemMint);
+  const [settlementFeeVaultPda] = findSettlementFeeVaultPda(ecosystemMint);
+  const [merchantPda] = findMerchantPda(ecosystemMint, merchantWallet);
+
+  const requestIndexBuffer = Buffer.alloc(8);
+  requestIndexBuffer.writeBigUInt64LE(BigInt(config.requestIndex));
 
   const [withdrawalRequestPda] = PublicKey.findProgramAddressSync(
     [
       Buffer.from("withdrawal_request"),
       merchantWallet.toBuffer(),
       ecosystemConfigPda.toBuffer(),
+      requestIndexBuffer,
     ],
     new PublicKey(PROGRAM_CONFIG.PROGRAM_ID)
   );
@@ -60,7 +74,7 @@ export async function approveWithdrawalRequest(
   );
 
   const [vaultPda] = PublicKey.findProgramAddressSync(
-    [Buffer.from(VAULT_SEED)],
+    [Buffer.from(VAULT_SEED), ecosystemMint.toBuffer()],
     new PublicKey(PROGRAM_CONFIG.PROGRAM_ID)
   );
 
@@ -87,6 +101,7 @@ export async function approveWithdrawalRequest(
     { pubkey: configPda, isSigner: false, isWritable: false },
     { pubkey: approver.publicKey, isSigner: true, isWritable: true },
     { pubkey: withdrawalRequestPda, isSigner: false, isWritable: true },
+    { pubkey: merchantWallet, isSigner: false, isWritable: true },
     { pubkey: merchantBalancePda, isSigner: false, isWritable: true },
     { pubkey: ecosystemConfigPda, isSigner: false, isWritable: true },
     { pubkey: ecosystemMint, isSigner: false, isWritable: false },
@@ -94,8 +109,11 @@ export async function approveWithdrawalRequest(
     { pubkey: outputMintProgram, isSigner: false, isWritable: false },
     { pubkey: vaultPda, isSigner: false, isWritable: true },
     { pubkey: vaultOutputTokenAccount, isSigner: false, isWritable: true },
+    { pubkey: merchantPda, isSigner: false, isWritable: false },
     { pubkey: merchantTokenAccount, isSigner: false, isWritable: true },
-    { pubkey: feeVaultPda, isSigner: false, isWritable: true },
+    { pubkey: feeVaultAuthorityPda, isSigner: false, isWritable: false },
+    { pubkey: settlementFeeVaultPda, isSigner: false, isWritable: true },
+    { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
   ];
 
   const instruction = {
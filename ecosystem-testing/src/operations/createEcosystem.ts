@@ -11,6 +11,7 @@ import BN from "bn.js";
 import {
   PROGRAM_CONFIG,
   INSTRUCTION_DISCRIMINATORS,
+  USDC_MINT,
 } from "../config/constants.js";
 import {
   findConfigPda,
@@ -19,6 +20,7 @@ import {
   findFeeVaultAuthorityPda,
   findFeeVaultPda,
   findCollateralVaultPda,
+  findSettlementFeeVaultPda,
 } from "../utils/pda.js";
 import {
   writeUint32LE,
@@ -52,11 +54,13 @@ export async function createEcosystem(
   );
   const [feeVaultPda] = findFeeVaultPda(mintKeypair.publicKey);
   const [collateralVaultPda] = findCollateralVaultPda(mintKeypair.publicKey);
+  const [settlementFeeVaultPda] = findSettlementFeeVaultPda(mintKeypair.publicKey);
 
   console.log(`Mint Authority PDA: ${mintAuthorityPda.toString()}`);
   console.log(`Ecosystem Config PDA: ${ecosystemConfigPda.toString()}`);
   console.log(`Fee Vault PDA: ${feeVaultPda.toString()}`);
   console.log(`Collateral Vault PDA: ${collateralVaultPda.toString()}`);
+  console.log(`Settlement Fee Vault PDA: ${settlementFeeVaultPda.toString()}`);
 
   const collateralTokenMint = new PublicKey(config.collateralTokenMint);
   const collateralTokenProgram = await getTokenProgram(
@@ -64,11 +68,21 @@ export async function createEcosystem(
     collateralTokenMint
   );
 
+  const settlementMint = new PublicKey(config.settlementMint || USDC_MINT);
+  const settlementTokenProgram = await getTokenProgram(
+    connection,
+    settlementMint
+  );
+
   const partnerWallet = config.ecosystemPartnerWallet
     ? new PublicKey(config.ecosystemPartnerWallet)
     : payer.publicKey;
 
-  const data = buildCreateEcosystemData(config, partnerWallet);
+  const data = buildCreateEcosystemData(
+    config,
+    partnerWallet,
+    settlementMint
+  );
 
   const modifyComputeUnits = ComputeBudgetProgram.setComputeUnitLimit({
     units: config.computeUnits || 400000,
@@ -84,12 +98,15 @@ export async function createEcosystem(
     { pubkey: collateralTokenMint, isSigner: false, isWritable: false },
     { pubkey: feeVaultPda, isSigner: false, isWritable: true },
     { pubkey: collateralVaultPda, isSigner: false, isWritable: true },
+    { pubkey: settlementMint, isSigner: false, isWritable: false },
+    { pubkey: settlementFeeVaultPda, isSigner: false, isWritable: true },
     {
       pubkey: new PublicKey(PROGRAM_CONFIG.TOKEN_2022_PROGRAM_ID),
       isSigner: false,
       isWritable: false,
     },
     { pubkey: collateralTokenProgram, isSigner: false, isWritable: false },
+    { pubkey: settlementTokenProgram, isSigner: false, isWritable: false },
     { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
   ];
@@ -158,7 +175,8 @@ function validateEcosystemConfig(config: EcosystemConfig): void {
 
 function buildCreateEcosystemData(
   config: EcosystemConfig,
-  partnerWallet: PublicKey
+  partnerWallet: PublicKey,
+  settlementMint: PublicKey
 ): Buffer {
   const discriminator = Buffer.from(
     INSTRUCTION_DISCRIMINATORS.CREATE_ECOSYSTEM
@@ -188,6 +206,9 @@ function buildCreateEcosystemData(
     parseInt(config.depositFeeBasisPoints || "25")
   );
   const collateralMint = new PublicKey(config.collateralTokenMint);
+  const redemptionFeeBuf = writeUint16LE(
+    parseInt(config.redemptionFeeBasisPoints || "0")
+  );
 
   return Buffer.concat([
     discriminator,
@@ -204,5 +225,7 @@ function buildCreateEcosystemData(
     withdrawalFeeBuf,
     depositFeeBuf,
     collateralMint.toBuffer(),
+    redemptionFeeBuf,
+    settlementMint.toBuffer(),
   ]);
 }
@@ -55,6 +55,14 @@ async function checkAccountExists(
           console.log(`merchant: ${merchant.toString()}`);
           console.log(`balance: ${balance.toString()}`);
           console.log(`ecosystem Mint: ${ecosystemMint.toString()}`);
+
+          if (accountInfo.data.length >= 96) {
+            const reservedBalance = accountInfo.data.readBigUInt64LE(80);
+            const requestCount = accountInfo.data.readBigUInt64LE(88);
+
+            console.log(`reserved balance: ${reservedBalance.toString()}`);
+            console.log(`withdrawal requests: ${requestCount.toString()}`);
+          }
         } catch (decodeError) {
           console.log(`${decodeError}`);
         }
@@ -0,0 +1,99 @@
+import {
+  Connection,
+  Keypair,
+  PublicKey,
+  SystemProgram,
+  Transaction,
+} from "@solana/web3.js";
+import {
+  PROGRAM_CONFIG,
+  INSTRUCTION_DISCRIMINATORS,
+} from "../config/constants.js";
+import {
+  findConfigPda,
+  findEcosystemConfigPda,
+  findMerchantPda,
+} from "../utils/pda.js";
+import {
+  sendAndConfirmTransactionWithRetry,
+  writeUint32LE,
+} from "../utils/helpers.js";
+import {
+  MerchantConfig,
+  OperationResult,
+  GlobalOptions,
+} from "../types/index.js";
+
+export async function registerMerchant(
+  connection: Connection,
+  payer: Keypair,
+  config: MerchantConfig,
+  options: GlobalOptions = { maxRetries: 3, fastMode: false, verbose: false }
+): Promise<OperationResult> {
+  console.log("\nRegistering merchant");
+
+  const ecosystemMint = new PublicKey(config.ecosystemMint);
+  const merchantWallet = new PublicKey(config.merchantWallet);
+  const payoutAddress = config.payoutAddress
+    ? new PublicKey(config.payoutAddress)
+    : merchantWallet;
+
+  const [configPda] = findConfigPda();
+  const [ecosystemConfigPda] = findEcosystemConfigPda(ecosystemMint);
+  const [merchantPda] = findMerchantPda(ecosystemMint, merchantWallet);
+
+  console.log(`Merchant PDA: ${merchantPda.toString()}`);
+  console.log(`Payout address: ${payoutAddress.toString()}`);
+
+  const nameBytes = Buffer.from(config.name);
+  const categoryBytes = Buffer.from(config.category);
+
+  const data = Buffer.concat([
+    Buffer.from(INSTRUCTION_DISCRIMINATORS.REGISTER_MERCHANT),
+    writeUint32LE(nameBytes.length),
+    nameBytes,
+    writeUint32LE(categoryBytes.length),
+    categoryBytes,
+    payoutAddress.toBuffer(),
+  ]);
+
+  const accounts = [
+    { pubkey: configPda, isSigner: false, isWritable: false },
+    { pubkey: payer.publicKey, isSigner: true, isWritable: true },
+    { pubkey: ecosystemMint, isSigner: false, isWritable: false },
+    { pubkey: ecosystemConfigPda, isSigner: false, isWritable: false },
+    { pubkey: merchantWallet, isSigner: false, isWritable: false },
+    { pubkey: merchantPda, isSigner: false, isWritable: true },
+    { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+  ];
+
+  const instruction = {
+    programId: new PublicKey(PROGRAM_CONFIG.PROGRAM_ID),
+    keys: accounts,
+    data,
+  };
+
+  const transaction = new Transaction().add(instruction);
+
+  const { blockhash } = await connection.getLatestBlockhash("confirmed");
+  transaction.recentBlockhash = blockhash;
+  transaction.feePayer = payer.publicKey;
+
+  console.log("Signing tx");
+  transaction.sign(payer);
+
+  const signature = await sendAndConfirmTransactionWithRetry(
+    connection,
+    transaction.serialize(),
+    {
+      skipPreflight: false,
+      preflightCommitment: "confirmed",
+      maxRetries: options.maxRetries,
+      commitment: "confirmed",
+      fastMode: options.fastMode,
+    }
+  );
+
+  console.log(`Merchant registered with addr ${config.merchantWallet}`);
+  return { signature };
+}
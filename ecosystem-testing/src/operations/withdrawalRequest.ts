@@ -5,11 +5,16 @@ import {
   SystemProgram,
   Transaction,
 } from "@solana/web3.js";
+import BN from "bn.js";
 import {
   PROGRAM_CONFIG,
   INSTRUCTION_DISCRIMINATORS,
 } from "../config/constants.js";
-import { findEcosystemConfigPda } from "../utils/pda.js";
+import {
+  findConfigPda,
+  findEcosystemConfigPda,
+  findMerchantPda,
+} from "../utils/pda.js";
 import { sendAndConfirmTransactionWithRetry } from "../utils/helpers.js";
 import {
   WithdrawalConfig,
@@ -30,7 +35,9 @@ export async function createWithdrawalRequest(
     ? new PublicKey(config.merchantWallet)
     : payer.publicKey;
 
+  const [configPda] = findConfigPda();
   const [ecosystemConfigPda] = findEcosystemConfigPda(ecosystemMint);
+  const [merchantPda] = findMerchantPda(ecosystemMint, merchantWallet);
 
   const [merchantBalancePda] = PublicKey.findProgramAddressSync(
     [
@@ -41,11 +48,23 @@ export async function createWithdrawalRequest(
     new PublicKey(PROGRAM_CONFIG.PROGRAM_ID)
   );
 
+  const merchantBalanceInfo = await connection.getAccountInfo(
+    merchantBalancePda
+  );
+  if (!merchantBalanceInfo) {
+    throw new Error("Merchant balance account not found");
+  }
+  // Each new request is seeded with the merchant's current request count
+  const requestIndex = merchantBalanceInfo.data.readBigUInt64LE(88);
+  const requestIndexBuffer = Buffer.alloc(8);
+  requestIndexBuffer.writeBigUInt64LE(requestIndex);
+
   const [withdrawalRequestPda] = PublicKey.findProgramAddressSync(
     [
       Buffer.from("withdrawal_request"),
       merchantWallet.toBuffer(),
       ecosystemConfigPda.toBuffer(),
+      requestIndexBuffer,
     ],
     new PublicKey(PROGRAM_CONFIG.PROGRAM_ID)
   );
@@ -53,15 +72,20 @@ export async function createWithdrawalRequest(
   console.log(`Merchant balance PDA: ${merchantBalancePda.toString()}`);
   console.log(`Withdrawal req PDA: ${withdrawalRequestPda.toString()}`);
 
-  const data = Buffer.from(
+  const discriminator = Buffer.from(
     INSTRUCTION_DISCRIMINATORS.CREATE_WITHDRAWAL_REQUEST
   );
+  const amountBuffer = Buffer.alloc(8);
+  new BN(config.amount).toArrayLike(Buffer, "le", 8).copy(amountBuffer);
+  const data = Buffer.concat([discriminator, amountBuffer]);
 
   const accounts = [
     { pubkey: payer.publicKey, isSigner: true, isWritable: true },
-    { pubkey: merchantBalancePda, isSigner: false, isWritable: false },
+    { pubkey: merchantBalancePda, isSigner: false, isWritable: true },
+    { pubkey: configPda, isSigner: false, isWritable: false },
     { pubkey: ecosystemConfigPda, isSigner: false, isWritable: false },
     { pubkey: ecosystemMint, isSigner: false, isWritable: false },
+    { pubkey: merchantPda, isSigner: false, isWritable: false },
     { pubkey: withdrawalRequestPda, isSigner: false, isWritable: true },
     { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
   ];
@@ -93,7 +117,7 @@ export async function createWithdrawalRequest(
     }
   );
 
-  console.log(`Withdrawal req created`);
+  console.log(`Withdrawal req ${requestIndex.toString()} created`);
   console.log(`Withdrawal request PDA ${withdrawalRequestPda.toString()}`);
   return { signature };
 }
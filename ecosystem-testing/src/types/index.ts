@@ -18,7 +18,9 @@ export interface Seeds {
   ECOSYSTEM_CONFIG: string;
   FEE_VAULT_AUTHORITY: string;
   FEE_VAULT: string;
+  SETTLEMENT_FEE_VAULT: string;
   COLLATERAL_VAULT: string;
+  MERCHANT: string;
 }
 
 export interface InstructionDiscriminators {
@@ -29,6 +31,7 @@ export interface InstructionDiscriminators {
   REMOVE_APPROVER: number[];
   CREATE_WITHDRAWAL_REQUEST: number[];
   APPROVE_WITHDRAWAL_REQUEST: number[];
+  REGISTER_MERCHANT: number[];
 }
 
 export interface DefaultConfig {
@@ -49,6 +52,8 @@ export interface EcosystemConfig {
   withdrawalFeeBasisPoints?: string;
   depositFeeBasisPoints?: string;
   collateralTokenMint: string;
+  redemptionFeeBasisPoints?: string;
+  settlementMint?: string;
   computeUnits?: number;
 }
 
@@ -72,13 +77,19 @@ export interface CliArgs {
   "max-minting-cap": string;
   "withdrawal-fee-basis-points": string;
   "deposit-fee-basis-points": string;
+  "redemption-fee-basis-points": string;
   "collateral-token-mint": string;
+  "settlement-mint": string;
   "ecosystem-mint": string;
   "user-collateral-account": string;
   amount: string;
   "approver-address": string;
   "merchant-wallet": string;
   "merchant-token-account": string;
+  "request-index": string;
+  "merchant-name": string;
+  "merchant-category": string;
+  "payout-address": string;
   verbose: boolean;
   help: boolean;
 }
@@ -86,12 +97,23 @@ export interface CliArgs {
 export interface WithdrawalConfig {
   ecosystemMint: string;
   merchantWallet?: string;
+  amount: string;
 }
 
 export interface ApprovalConfig {
   ecosystemMint: string;
   merchantWallet: string;
   merchantTokenAccount: string;
+  requestIndex: string;
+  settlementMint?: string;
+}
+
+export interface MerchantConfig {
+  ecosystemMint: string;
+  merchantWallet: string;
+  name: string;
+  category: string;
+  payoutAddress?: string;
 }
 
 export interface ApproverConfig {
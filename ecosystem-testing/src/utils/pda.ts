@@ -39,9 +39,26 @@ export function findFeeVaultPda(mint: PublicKey): [PublicKey, number] {
   );
 }
 
+export function findSettlementFeeVaultPda(mint: PublicKey): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from(SEEDS.SETTLEMENT_FEE_VAULT), mint.toBuffer()],
+    PROGRAM_PUBLIC_KEY
+  );
+}
+
 export function findCollateralVaultPda(mint: PublicKey): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
     [Buffer.from(SEEDS.COLLATERAL_VAULT), mint.toBuffer()],
     PROGRAM_PUBLIC_KEY
   );
 }
+
+export function findMerchantPda(
+  mint: PublicKey,
+  merchantWallet: PublicKey
+): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from(SEEDS.MERCHANT), mint.toBuffer(), merchantWallet.toBuffer()],
+    PROGRAM_PUBLIC_KEY
+  );
+}
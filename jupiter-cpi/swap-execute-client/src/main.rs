@@ -28,6 +28,8 @@ const MAX_RETRIES: usize = 25;
 const SWAP_AMOUNT: u64 = 1_000_00;
 const PURCHASE_AMOUNT: u64 = 1_000_00;
 const PURCHASE_REF: &str = "REF12345";
+// Price feed set for the ecosystem with `set_price_oracle`, None if it has no oracle
+const PRICE_FEED: Option<&str> = None;
 const EXCLUDED_DEXES: &str = "Obric V2,Moonit";
 
 lazy_static! {
@@ -51,10 +53,11 @@ async fn ensure_vault_token_accounts(
     rpc: &Arc<RpcClient>,
     keypair: &Keypair,
     program_id: &Pubkey,
+    ecosystem_token: &Pubkey,
     input_token: &Pubkey,
     output_token: &Pubkey
 ) -> Result<()> {
-    let (vault, _) = Pubkey::find_program_address(&[VAULT_SEED], program_id);
+    let (vault, _) = Pubkey::find_program_address(&[VAULT_SEED, ecosystem_token.as_ref()], program_id);
     
     let input_mint_account = match rpc.get_account(input_token) {
         Ok(acc) => acc,
@@ -191,7 +194,7 @@ async fn main() -> Result<()> {
     let jup_api = JupiterSwapApiClient::new(api_url);
     
     let program_id = Pubkey::from_str(PROGRAM_ID)?;
-    let (vault, _) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+    let (vault, _) = Pubkey::find_program_address(&[VAULT_SEED, ecosystem_token.as_ref()], &program_id);
     let vault_input = get_associated_token_address(&vault, &input_token);
     let vault_output = get_associated_token_address(&vault, &output_token);
 
@@ -199,6 +202,7 @@ async fn main() -> Result<()> {
         &rpc, 
         &keypair,
         &program_id,
+        &ecosystem_token,
         &input_token, 
         &output_token
     ).await?;
@@ -224,6 +228,7 @@ async fn main() -> Result<()> {
     let quote = jup_api.quote(&quote_request).await?;
     
     println!("Quote : {} -> {}", quote.in_amount, quote.out_amount);
+    println!("Min out: {}", quote.other_amount_threshold);
     println!("Price impact: {}%", quote.price_impact_pct);
     println!("Route size: {}", quote.route_plan.len());
     
@@ -231,6 +236,9 @@ async fn main() -> Result<()> {
         println!("Route {}: {} ({}%)", i, route.swap_info.label, route.percent);
     }
     
+    // Least USDC the vault must receive, `swap` reverts with `SlippageExceeded` below it
+    let min_settlement_out = quote.other_amount_threshold;
+
     let swap_resp = jup_api.swap_instructions(&SwapRequest {
         user_public_key: vault,
         quote_response: quote,
@@ -252,6 +260,7 @@ async fn main() -> Result<()> {
         &wallet, &ecosystem_token, &token_program_id
     );
     
+    let (config, _) = Pubkey::find_program_address(&[b"config"], &program_id);
     let (ecosystem_config, _) = Pubkey::find_program_address(
         &[b"ecosystem_config", ecosystem_token.as_ref()], &program_id
     );
@@ -264,11 +273,19 @@ async fn main() -> Result<()> {
     let (merchant_balance, _) = Pubkey::find_program_address(
         &[b"merchant_balance", MERCHANT_WALLET.as_ref(), ecosystem_token.as_ref()], &program_id
     );
+    let (registered_merchant, _) = Pubkey::find_program_address(
+        &[b"merchant", ecosystem_token.as_ref(), MERCHANT_WALLET.as_ref()], &program_id
+    );
+    let purchase_reference_hash = Sha256::digest(PURCHASE_REF.as_bytes());
+    let (purchase_receipt, _) = Pubkey::find_program_address(
+        &[b"purchase_receipt", MERCHANT_WALLET.as_ref(), purchase_reference_hash.as_slice()], &program_id
+    );
     
     let mut ix_data = get_disc("swap").to_vec();
     ix_data.extend_from_slice(&PURCHASE_AMOUNT.to_le_bytes());
     ix_data.extend_from_slice(&(PURCHASE_REF.len() as u32).to_le_bytes());
     ix_data.extend_from_slice(PURCHASE_REF.as_bytes());
+    ix_data.extend_from_slice(&min_settlement_out.to_le_bytes());
     ix_data.extend_from_slice(&(swap_resp.swap_instruction.data.len() as u32).to_le_bytes());
     ix_data.extend_from_slice(&swap_resp.swap_instruction.data);
     
@@ -283,7 +300,8 @@ async fn main() -> Result<()> {
         AccountMeta::new(vault_output, false),
         AccountMeta::new_readonly(Pubkey::from_str(JUP_PROGRAM_ID)?, false),
         AccountMeta::new(ecosystem_token, false),
-        AccountMeta::new_readonly(ecosystem_config, false),
+        AccountMeta::new_readonly(config, false),
+        AccountMeta::new(ecosystem_config, false),
         AccountMeta::new(user_token_account, false),
         AccountMeta::new_readonly(token_program_id, false),
         AccountMeta::new_readonly(fee_vault_authority, false),
@@ -291,6 +309,12 @@ async fn main() -> Result<()> {
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new(merchant_balance, false),
         AccountMeta::new_readonly(*MERCHANT_WALLET, false),
+        AccountMeta::new_readonly(registered_merchant, false),
+        AccountMeta::new(purchase_receipt, false),
+        // Merchant payout account and settlement fee vault are only used by instant settlement merchants
+        AccountMeta::new_readonly(program_id, false),
+        AccountMeta::new_readonly(program_id, false),
+        AccountMeta::new_readonly(PRICE_FEED.map_or(Ok(program_id), Pubkey::from_str)?, false),
         AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
     ];
     
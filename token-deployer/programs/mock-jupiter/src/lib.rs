@@ -1,7 +1,7 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{
+use anchor_spl::token_interface::{
     self, 
-    Token, 
+    TokenInterface, 
     TokenAccount, 
     Burn, 
     Mint, 
@@ -30,7 +30,7 @@ pub mod mock_jupiter {
             return err!(ErrorCode::EmptyRoute);
         }
         
-        token::burn(
+        token_interface::burn(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Burn {
@@ -58,9 +58,9 @@ pub mod mock_jupiter {
         let seeds = &[MINT_AUTH_SEED, &[bump]];
         let signer_seeds = &[&seeds[..]];
         
-        token::mint_to(
+        token_interface::mint_to(
             CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.destination_token_program.to_account_info(),
                 MintTo {
                     mint: ctx.accounts.destination_mint.to_account_info(),
                     to: ctx.accounts.user_destination_token_account.to_account_info(),
@@ -80,23 +80,23 @@ pub mod mock_jupiter {
 
 #[derive(Accounts)]
 pub struct RouteAccounts<'info> {
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     
     #[account(mut)]
     pub user_transfer_authority: Signer<'info>,
     
     #[account(mut)]
-    pub user_source_token_account: Account<'info, TokenAccount>,
+    pub user_source_token_account: InterfaceAccount<'info, TokenAccount>,
     
     #[account(mut)]
-    pub user_destination_token_account: Account<'info, TokenAccount>,
+    pub user_destination_token_account: InterfaceAccount<'info, TokenAccount>,
     
     /// CHECK: Optional account - ownership check is disabled for mock
     #[account(mut)]
     pub destination_token_account: Option<UncheckedAccount<'info>>,
     
     #[account(mut)]
-    pub destination_mint: Account<'info, Mint>,
+    pub destination_mint: InterfaceAccount<'info, Mint>,
     
     /// CHECK: Optional account - ownership check is disabled for mock
     #[account(mut)]
@@ -109,7 +109,7 @@ pub struct RouteAccounts<'info> {
     pub program: UncheckedAccount<'info>,
     
     #[account(mut)]
-    pub source_mint: Account<'info, Mint>,
+    pub source_mint: InterfaceAccount<'info, Mint>,
     
     /// CHECK: PDA owned by this program that signs for mint
     #[account(
@@ -117,6 +117,10 @@ pub struct RouteAccounts<'info> {
         bump,
     )]
     pub mint_authority: UncheckedAccount<'info>,
+
+    /// Program of `destination_mint`, `token_program` only burns the source tokens so routes
+    /// can swap between legacy SPL Token and Token-2022 mints
+    pub destination_token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
@@ -1,25 +1,71 @@
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::{instruction::Instruction, program::invoke_signed};
+use anchor_lang::solana_program::{hash::hash, instruction::Instruction, program::{invoke, invoke_signed}};
+use anchor_lang::system_program;
 use anchor_spl::token_2022::{
     set_authority, mint_to, Token2022,
     SetAuthority, MintTo,
 };
 use anchor_spl::token_interface::{
     transfer_checked, TransferChecked,
-    Mint, TokenAccount, burn, Burn, TokenInterface
+    Mint, TokenAccount, burn, Burn, TokenInterface,
+    close_account, CloseAccount
 };
 use anchor_spl::token_2022::spl_token_2022::instruction::AuthorityType;
+use anchor_spl::associated_token::AssociatedToken;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_metadata_interface::state::{Field, TokenMetadata};
 use std::str::FromStr;
-use jupiter_aggregator::program::Jupiter;
 
 declare_program!(jupiter_aggregator);
 declare_id!("CEzsTf7eM9ac1kGx7DuZHdXv8b4mLPQBbRzrQcMJmJBh");
 
 const VAULT_SEED: &[u8] = b"vault";
+// Cluster addresses are picked by cargo feature: `devnet` or `localnet`, mainnet without either
+#[cfg(all(feature = "devnet", feature = "localnet"))]
+compile_error!("The `devnet` and `localnet` features select different clusters, enable at most one");
+#[cfg(feature = "localnet")]
+#[constant]
+const JUP_PROGRAM_ID: &str = "JA1v2pi2o7yG79hHFqADtSgPZHmMspnKHGcecPAeK9pa";
+#[cfg(not(feature = "localnet"))]
+#[constant]
 const JUP_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
+#[cfg(feature = "devnet")]
+const USDC_MINT_STR: &str = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+// On localnet the mainnet USDC address is loaded from `tests/fixtures/usdc-mint.json`
+#[cfg(not(feature = "devnet"))]
 const USDC_MINT_STR: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
 const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
 const TOKEN2022_PROGRAM: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+const PYTH_RECEIVER_PROGRAM: &str = "rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ";
+/// Anchor discriminator of the Pyth receiver `PriceUpdateV2` account
+const PYTH_PRICE_UPDATE_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+/// Oracle prices are `price * 10^exponent` with `exponent` within +/- this bound
+const MAX_PRICE_EXPONENT: i32 = 18;
+const MAX_ROLE_MEMBERS: usize = 10;
+const MAX_APPROVERS: usize = 10;
+const MAX_SETTLEMENT_MINTS: usize = 5;
+const ALL_PAUSE_FLAGS: u8 = 0b0111_1111;
+/// Minimum notice in seconds partners get before a fee increase takes effect
+const FEE_INCREASE_NOTICE: i64 = 7 * 24 * 60 * 60;
+/// Bumped whenever fields are appended to `MerchantBalance`, older accounts are grown
+/// to `MERCHANT_BALANCE_SPACE` by `migrate_merchant_balance`
+const MERCHANT_BALANCE_VERSION: u8 = 1;
+const MERCHANT_BALANCE_SPACE: usize = 8 + 32 + 8 + 32 + 8 + 8 + 1;
+const MAX_MERCHANT_NAME_LEN: usize = 32;
+const MAX_MERCHANT_CATEGORY_LEN: usize = 32;
+const MERCHANT_SPACE: usize = 8 + 32 + 32 + (4 + MAX_MERCHANT_NAME_LEN) + (4 + MAX_MERCHANT_CATEGORY_LEN) + 32 + 1 + 1 + 8 + 1;
+const WITHDRAWAL_RECEIPT_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1 + 33 + 8 + 8 + 2 + 4;
+const MAX_PURCHASE_REFERENCE_LEN: usize = 64;
+const PURCHASE_RECEIPT_SPACE: usize = 8 + 32 + 32 + 32 + (4 + MAX_PURCHASE_REFERENCE_LEN) + 8 + 8 + 8 + 1 + 8;
+const WITHDRAWAL_REQUEST_SPACE: usize = WITHDRAWAL_RECEIPT_SPACE + (32 * MAX_APPROVERS);
+/// Bumped whenever fields are appended to `Config`, older configs are grown
+/// to `CONFIG_SPACE` by `migrate_config`
+const CONFIG_VERSION: u8 = 1;
+const CONFIG_SPACE: usize = 8 + 32 + 1 + 4 + (32 * MAX_APPROVERS) + (1 + 32) + 4 + (33 * MAX_ROLE_MEMBERS) + 1 + 4 + (32 * MAX_SETTLEMENT_MINTS) + 1;
+/// Bumped whenever fields are appended to `EcosystemConfig`, older accounts are grown
+/// to `ECOSYSTEM_CONFIG_SPACE` by `migrate_ecosystem_config`
+const ECOSYSTEM_CONFIG_VERSION: u8 = 4;
+const ECOSYSTEM_CONFIG_SPACE: usize = 8 + 32 + 8 + 2 + 2 + 32 + 1 + 8 + 32 + 2 + 8 + (1 + 2 + 2 + 2 + 8) + 1 + 8 + 1 + 1 + 32 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + (1 + 32) + 2 + 8 + 1 + 8 + 8 + (1 + 32) + 8 + 32;
 
 pub fn jupiter_program_id() -> Pubkey {
     Pubkey::from_str(JUP_PROGRAM_ID).unwrap_or_else(|_| panic!("Invalid Jupiter program ID"))
@@ -37,14 +83,362 @@ pub fn token2022_program_id() -> Pubkey {
     Pubkey::from_str(TOKEN2022_PROGRAM).unwrap_or_else(|_| panic!("Invalid token2022 program"))
 }
 
+pub fn pyth_receiver_program_id() -> Pubkey {
+    Pubkey::from_str(PYTH_RECEIVER_PROGRAM).unwrap_or_else(|_| panic!("Invalid Pyth receiver program"))
+}
+
+/// Reads `(price, conf, exponent, publish_time)` from either a Pyth pull-oracle `PriceUpdateV2`
+/// account for the ecosystem's `price_feed_id`, or the ecosystem's `PriceFeed` account owned by
+/// this program, which has no confidence interval.
+fn read_oracle_price(
+    price_feed: &AccountInfo,
+    ecosystem_config: &EcosystemConfig,
+    ecosystem_mint: &Pubkey,
+) -> Result<(i64, u64, i32, i64)> {
+    let data = price_feed.try_borrow_data()?;
+
+    if *price_feed.owner == pyth_receiver_program_id() {
+        // discriminator, write authority, verification level, then the price message
+        // starting with the 32 byte feed id. Only fully verified updates are accepted.
+        require!(
+            data.len() >= 101 && data[..8] == PYTH_PRICE_UPDATE_DISCRIMINATOR && data[40] == 1,
+            ErrorCode::InvalidPriceFeed
+        );
+        let read = |start: usize, len: usize| -> &[u8] { &data[start..start + len] };
+
+        // The update's write authority can post any verified feed into the same account
+        require!(read(41, 32) == ecosystem_config.price_feed_id, ErrorCode::PriceFeedIdMismatch);
+
+        let price = i64::from_le_bytes(read(73, 8).try_into().unwrap());
+        let conf = u64::from_le_bytes(read(81, 8).try_into().unwrap());
+        let exponent = i32::from_le_bytes(read(89, 4).try_into().unwrap());
+        let publish_time = i64::from_le_bytes(read(93, 8).try_into().unwrap());
+
+        Ok((price, conf, exponent, publish_time))
+    } else if *price_feed.owner == crate::ID {
+        let feed = PriceFeed::try_deserialize(&mut &data[..])?;
+
+        require_keys_eq!(feed.ecosystem_mint, *ecosystem_mint, ErrorCode::InvalidPriceFeed);
+
+        Ok((feed.price, 0, feed.exponent, feed.publish_time))
+    } else {
+        err!(ErrorCode::InvalidPriceFeed)
+    }
+}
+
+/// Value of `amount_in` of the input token in output tokens at the oracle price. Fails when
+/// the oracle price is stale, or less certain than the ecosystem's max deviation.
+fn oracle_value(
+    price_feed: &AccountInfo,
+    ecosystem_config: &EcosystemConfig,
+    ecosystem_mint: &Pubkey,
+    amount_in: u64,
+    input_decimals: u8,
+    output_decimals: u8,
+) -> Result<u128> {
+    let (price, conf, exponent, publish_time) = read_oracle_price(price_feed, ecosystem_config, ecosystem_mint)?;
+
+    require!(price > 0, ErrorCode::InvalidPriceFeed);
+    require!(
+        (-MAX_PRICE_EXPONENT..=MAX_PRICE_EXPONENT).contains(&exponent),
+        ErrorCode::InvalidPriceFeed
+    );
+    require!(
+        Clock::get()?.unix_timestamp.saturating_sub(publish_time) <= ecosystem_config.max_price_staleness,
+        ErrorCode::StalePriceFeed
+    );
+    require!(
+        (conf as u128) * 10000 <= (price as u128) * ecosystem_config.max_price_deviation_bps as u128,
+        ErrorCode::PriceConfidenceTooWide
+    );
+
+    let scale = exponent
+        .checked_add(output_decimals as i32)
+        .and_then(|x| x.checked_sub(input_decimals as i32))
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    let value = (amount_in as u128)
+        .checked_mul(price as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    let expected_out = if scale >= 0 {
+        10u128
+            .checked_pow(scale as u32)
+            .and_then(|x| value.checked_mul(x))
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+    } else {
+        10u128
+            .checked_pow(scale.unsigned_abs())
+            .map(|x| value / x)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+    };
+
+    Ok(expected_out)
+}
+
+/// Fails when the oracle price is stale, or `amount_out` of the output token is more than
+/// the ecosystem's max deviation below what `amount_in` of the input token is worth at the oracle price.
+fn check_oracle_price(
+    price_feed: &AccountInfo,
+    ecosystem_config: &EcosystemConfig,
+    ecosystem_mint: &Pubkey,
+    amount_in: u64,
+    input_decimals: u8,
+    amount_out: u64,
+    output_decimals: u8,
+) -> Result<()> {
+    let expected_out = oracle_value(
+        price_feed,
+        ecosystem_config,
+        ecosystem_mint,
+        amount_in,
+        input_decimals,
+        output_decimals,
+    )?;
+
+    let min_out = expected_out
+        .checked_mul(10000 - ecosystem_config.max_price_deviation_bps as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?
+        / 10000;
+
+    msg!("Oracle expected output: {}, minimum: {}", expected_out, min_out);
+
+    require!(amount_out as u128 >= min_out, ErrorCode::PriceDeviationExceeded);
+
+    Ok(())
+}
+
+/// Reverse of `check_oracle_price` for buy-backs: fails when `collateral_received` is worth more
+/// than the ecosystem's max deviation less than the `settlement_spent` on it at the oracle price.
+fn check_oracle_buy_back(
+    price_feed: &AccountInfo,
+    ecosystem_config: &EcosystemConfig,
+    ecosystem_mint: &Pubkey,
+    settlement_spent: u64,
+    settlement_decimals: u8,
+    collateral_received: u64,
+    collateral_decimals: u8,
+) -> Result<()> {
+    let collateral_value = oracle_value(
+        price_feed,
+        ecosystem_config,
+        ecosystem_mint,
+        collateral_received,
+        collateral_decimals,
+        settlement_decimals,
+    )?;
+
+    let min_value = (settlement_spent as u128)
+        .checked_mul(10000 - ecosystem_config.max_price_deviation_bps as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?
+        / 10000;
+
+    msg!("Oracle value of bought back collateral: {}, minimum: {}", collateral_value, min_value);
+
+    require!(collateral_value >= min_value, ErrorCode::PriceDeviationExceeded);
+
+    Ok(())
+}
+
+/// Fails unless a Jupiter `route` swaps from `source` into `destination` with the vault as the user,
+/// so merchant supplied refund routes can't send the bought back collateral anywhere else.
+fn require_route_accounts(
+    remaining_accounts: &[AccountInfo],
+    vault: Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+) -> Result<()> {
+    // token program, user transfer authority, user source and user destination token accounts
+    require!(
+        remaining_accounts.len() > 3
+            && *remaining_accounts[1].key == vault
+            && *remaining_accounts[2].key == source
+            && *remaining_accounts[3].key == destination,
+        ErrorCode::InvalidRefundSwap
+    );
+
+    Ok(())
+}
+
+fn validate_fee_basis_points(
+    deposit_fee_basis_points: u16,
+    withdrawal_fee_basis_points: u16,
+    redemption_fee_basis_points: u16,
+) -> Result<()> {
+    require!(
+        deposit_fee_basis_points <= 10000
+            && withdrawal_fee_basis_points <= 10000
+            && redemption_fee_basis_points <= 10000,
+        ErrorCode::InvalidFeePercentage
+    );
+
+    Ok(())
+}
+
+/// Fails when `flag` is paused either globally or for the ecosystem.
+fn require_not_paused(config: &Config, ecosystem_config: &EcosystemConfig, flag: PauseFlag) -> Result<()> {
+    require!(
+        config.pause_flags & flag.bit() == 0 && ecosystem_config.pause_flags & flag.bit() == 0,
+        ErrorCode::FreezeStateActive
+    );
+
+    Ok(())
+}
+
+/// Solvency invariants checked after instructions that move reserves.
+#[derive(Debug, Clone, Copy)]
+enum Invariant {
+    /// Collateral vault holds at least one collateral token per uSP in circulation
+    CollateralCoversSupply,
+    /// Ecosystem vault holds at least the settlement tokens owed to merchant balances
+    SettlementCoversMerchantBalances,
+}
+
+fn check_invariant(invariant: Invariant, holds: bool) -> Result<()> {
+    if !holds {
+        msg!("Invariant violated: {:?}", invariant);
+        return err!(ErrorCode::InvariantViolation);
+    }
+
+    Ok(())
+}
+
+/// Re-reads the collateral vault and uSP mint after CPIs and checks 1:1 backing.
+fn require_collateral_covers_supply<'info>(
+    collateral_vault: &mut InterfaceAccount<'info, TokenAccount>,
+    mint: &mut InterfaceAccount<'info, Mint>,
+) -> Result<()> {
+    collateral_vault.reload()?;
+    mint.reload()?;
+
+    check_invariant(
+        Invariant::CollateralCoversSupply,
+        collateral_vault.amount >= mint.supply,
+    )
+}
+
+/// Re-reads the ecosystem vault after CPIs and checks it covers all merchant balances.
+fn require_settlement_covers_merchant_balances<'info>(
+    vault_output_token_account: &mut InterfaceAccount<'info, TokenAccount>,
+    ecosystem_config: &EcosystemConfig,
+) -> Result<()> {
+    vault_output_token_account.reload()?;
+
+    check_invariant(
+        Invariant::SettlementCoversMerchantBalances,
+        vault_output_token_account.amount >= ecosystem_config.total_merchant_balance,
+    )
+}
+
+/// Withdrawal fee charged on a settlement token payout to a merchant. Any non-zero fee rate
+/// charges at least one base unit.
+fn withdrawal_fee(amount: u64, withdrawal_fee_basis_points: u16) -> Result<u64> {
+    let fee_amount = amount
+        .checked_mul(withdrawal_fee_basis_points as u64)
+        .ok_or(ErrorCode::ArithmeticOverflow)?
+        .checked_div(10000)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+    if withdrawal_fee_basis_points > 0 && fee_amount == 0 {
+        Ok(1)
+    } else {
+        Ok(fee_amount)
+    }
+}
+
+/// Tops up `account` so it stays rent exempt once it grows to `new_len`. Token-2022
+/// reallocates the mint itself when metadata grows, but the lamports must already be there.
+fn fund_realloc<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let required_lamports = Rent::get()?.minimum_balance(new_len);
+    let current_lamports = account.lamports();
+
+    if required_lamports > current_lamports {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            required_lamports - current_lamports,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Approved and rejected withdrawal requests are kept as receipts. Once their approvals
+/// are cleared they shrink to the receipt size and the freed rent goes back to the merchant.
+fn shrink_to_receipt<'info>(
+    withdrawal_request: &AccountInfo<'info>,
+    merchant: &AccountInfo<'info>,
+) -> Result<()> {
+    let required_lamports = Rent::get()?.minimum_balance(WITHDRAWAL_RECEIPT_SPACE);
+    let excess_lamports = withdrawal_request.lamports().saturating_sub(required_lamports);
+
+    withdrawal_request.realloc(WITHDRAWAL_RECEIPT_SPACE, false)?;
+
+    **withdrawal_request.try_borrow_mut_lamports()? -= excess_lamports;
+    **merchant.try_borrow_mut_lamports()? += excess_lamports;
+
+    Ok(())
+}
+
+/// Forwards a Jupiter route to the aggregator with the ecosystem vault signing as the user.
+fn invoke_jupiter<'info>(
+    jupiter_program: Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+    vault: Pubkey,
+    data: Vec<u8>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let accounts: Vec<AccountMeta> = remaining_accounts
+        .iter()
+        .map(|acc| {
+            let is_signer = acc.key == &vault;
+            AccountMeta {
+                pubkey: *acc.key,
+                is_signer,
+                is_writable: acc.is_writable,
+            }
+        })
+        .collect();
+
+    let accounts_infos: Vec<AccountInfo> = remaining_accounts
+        .iter()
+        .map(|acc| AccountInfo { ..acc.clone() })
+        .collect();
+
+    invoke_signed(
+        &Instruction {
+            program_id: jupiter_program,
+            accounts,
+            data,
+        },
+        &accounts_infos,
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
 #[program]
 pub mod token_deployer {
     use super::*;
 
     pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
         ctx.accounts.config.owner = ctx.accounts.payer.key();
-        ctx.accounts.config.global_freeze = false;
+        ctx.accounts.config.pause_flags = 0;
         ctx.accounts.config.approvers = Vec::new();
+        ctx.accounts.config.pending_owner = None;
+        ctx.accounts.config.role_members = Vec::new();
+        ctx.accounts.config.approval_threshold = 1;
+        ctx.accounts.config.allowed_settlement_mints = vec![usdc_mint_id()];
+        ctx.accounts.config.version = CONFIG_VERSION;
         
         emit!(ProgramInitialized {
             owner: ctx.accounts.payer.key(),
@@ -54,10 +448,116 @@ pub mod token_deployer {
         Ok(())
     }
 
-    pub fn add_approver(ctx: Context<ManageApprover>, approver: Pubkey) -> Result<()> {
+    pub fn propose_owner(ctx: Context<ProposeOwner>, new_owner: Pubkey) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        ctx.accounts.config.pending_owner = Some(new_owner);
+
+        emit!(OwnershipTransferStarted {
+            owner: ctx.accounts.config.owner,
+            pending_owner: new_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_owner(ctx: Context<ManageOwnership>) -> Result<()> {
+        let pending_owner = ctx.accounts.config.pending_owner
+            .ok_or(ErrorCode::NoPendingOwnershipTransfer)?;
+
+        require!(ctx.accounts.payer.key() == pending_owner, ErrorCode::Unauthorized);
+
+        let previous_owner = ctx.accounts.config.owner;
+        ctx.accounts.config.owner = pending_owner;
+        ctx.accounts.config.pending_owner = None;
+
+        emit!(OwnershipTransferred {
+            previous_owner,
+            new_owner: pending_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_owner_transfer(ctx: Context<ManageOwnership>) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let pending_owner = ctx.accounts.config.pending_owner
+            .ok_or(ErrorCode::NoPendingOwnershipTransfer)?;
+
+        ctx.accounts.config.pending_owner = None;
+
+        emit!(OwnershipTransferCancelled {
+            owner: ctx.accounts.config.owner,
+            cancelled_owner: pending_owner,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn grant_role(ctx: Context<ManageRole>, member: Pubkey, role: Role) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let role_members = &mut ctx.accounts.config.role_members;
+
+        match role_members.iter_mut().find(|x| x.member == member) {
+            Some(role_member) => {
+                require!(role_member.roles & role.bit() == 0, ErrorCode::RoleAlreadyGranted);
+                role_member.roles |= role.bit();
+            }
+            None => {
+                require!(role_members.len() < MAX_ROLE_MEMBERS, ErrorCode::TooManyRoleMembers);
+                role_members.push(RoleMember {
+                    member,
+                    roles: role.bit(),
+                });
+            }
+        }
+
+        emit!(RoleGranted {
+            member,
+            role,
+            granted_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn revoke_role(ctx: Context<ManageRole>, member: Pubkey, role: Role) -> Result<()> {
         require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let role_members = &mut ctx.accounts.config.role_members;
+
+        let position = role_members
+            .iter()
+            .position(|x| x.member == member && x.roles & role.bit() != 0);
+        require!(position.is_some(), ErrorCode::RoleNotGranted);
+
+        let position = position.unwrap();
+        role_members[position].roles &= !role.bit();
+        if role_members[position].roles == 0 {
+            role_members.remove(position);
+        }
+
+        emit!(RoleRevoked {
+            member,
+            role,
+            revoked_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_approver(ctx: Context<ManageApprover>, approver: Pubkey) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::ApproverAdmin), ErrorCode::Unauthorized);
         
         require!(!ctx.accounts.config.approvers.contains(&approver), ErrorCode::ApproverAlreadyExists);
+        require!(ctx.accounts.config.approvers.len() < MAX_APPROVERS, ErrorCode::TooManyApprovers);
         
         ctx.accounts.config.approvers.push(approver);
         
@@ -71,12 +571,17 @@ pub mod token_deployer {
     }
 
     pub fn remove_approver(ctx: Context<ManageApprover>, approver: Pubkey) -> Result<()> {
-        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::ApproverAdmin), ErrorCode::Unauthorized);
         
         let position = ctx.accounts.config.approvers.iter().position(|&x| x == approver);
         require!(position.is_some(), ErrorCode::ApproverNotFound);
         
         ctx.accounts.config.approvers.remove(position.unwrap());
+
+        require!(
+            ctx.accounts.config.approvers.len() >= ctx.accounts.config.approval_threshold as usize,
+            ErrorCode::InvalidApprovalThreshold
+        );
         
         emit!(ApproverRemoved {
             approver,
@@ -91,26 +596,236 @@ pub mod token_deployer {
         Ok(ctx.accounts.config.approvers.clone())
     }
 
-    pub fn create_ecosystem(ctx: Context<CreateEcosystem>, args: TokenMetadataArgs) -> Result<()> {
+    pub fn get_reserve_status(ctx: Context<GetReserveStatus>) -> Result<ReserveStatus> {
+        // `close_ecosystem` closes both vaults, a closed ecosystem reports them empty
+        let closed = ctx.accounts.ecosystem_config.status == EcosystemStatus::Closed;
+        let vault_balance = |vault: &Option<InterfaceAccount<'_, TokenAccount>>| -> Result<u64> {
+            match vault {
+                Some(vault) => Ok(vault.amount),
+                None => {
+                    require!(closed, ErrorCode::MissingVaultAccount);
+                    Ok(0)
+                }
+            }
+        };
+
+        let mint_supply = ctx.accounts.mint.supply;
+        let collateral_vault_balance = vault_balance(&ctx.accounts.collateral_vault)?;
+        let fee_vault_balance = vault_balance(&ctx.accounts.fee_vault)?;
+
+        // Collateral backing each uSP in basis points, None while nothing is minted
+        let collateralization_ratio_bps = if mint_supply == 0 {
+            None
+        } else {
+            let ratio = (collateral_vault_balance as u128)
+                .checked_mul(10000)
+                .ok_or(ErrorCode::ArithmeticOverflow)?
+                .checked_div(mint_supply as u128)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            Some(u64::try_from(ratio).map_err(|_| ErrorCode::ArithmeticOverflow)?)
+        };
+
+        let reserve_status = ReserveStatus {
+            mint_supply,
+            collateral_vault_balance,
+            fee_vault_balance,
+            merchant_balances_owed: ctx.accounts.ecosystem_config.total_merchant_balance,
+            collateralization_ratio_bps,
+        };
+
+        emit!(ReserveSnapshot {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            mint_supply: reserve_status.mint_supply,
+            collateral_vault_balance: reserve_status.collateral_vault_balance,
+            fee_vault_balance: reserve_status.fee_vault_balance,
+            merchant_balances_owed: reserve_status.merchant_balances_owed,
+            collateralization_ratio_bps: reserve_status.collateralization_ratio_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(reserve_status)
+    }
+
+    /// Sets the global approval threshold, at most the number of approvers. 0 is accepted, see
+    /// `Config::approval_threshold`.
+    pub fn set_approval_threshold(ctx: Context<SetApprovalThreshold>, approval_threshold: u8) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::ApproverAdmin), ErrorCode::Unauthorized);
+
+        require!(
+            approval_threshold as usize <= ctx.accounts.config.approvers.len(),
+            ErrorCode::InvalidApprovalThreshold
+        );
+
+        let old_approval_threshold = ctx.accounts.config.approval_threshold;
+        ctx.accounts.config.approval_threshold = approval_threshold;
+
+        emit!(ApprovalThresholdUpdated {
+            old_approval_threshold,
+            new_approval_threshold: approval_threshold,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_ecosystem_approval_threshold(
+        ctx: Context<SetEcosystemApprovalThreshold>,
+        approval_threshold: u8,
+        high_value_amount: u64,
+        high_value_approval_threshold: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::ApproverAdmin), ErrorCode::Unauthorized);
+
+        let approvers_count = ctx.accounts.config.approvers.len();
+
+        // 0 falls back to the global threshold
+        require!(
+            approval_threshold as usize <= approvers_count,
+            ErrorCode::InvalidApprovalThreshold
+        );
+
+        if high_value_amount > 0 {
+            let base_threshold = if approval_threshold > 0 {
+                approval_threshold
+            } else {
+                ctx.accounts.config.approval_threshold
+            };
+
+            require!(
+                high_value_approval_threshold >= base_threshold
+                    && high_value_approval_threshold as usize <= approvers_count,
+                ErrorCode::InvalidApprovalThreshold
+            );
+        }
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.approval_threshold = approval_threshold;
+        ecosystem_config.high_value_amount = high_value_amount;
+        ecosystem_config.high_value_approval_threshold = high_value_approval_threshold;
+
+        emit!(EcosystemApprovalThresholdUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            approval_threshold,
+            high_value_amount,
+            high_value_approval_threshold,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_settlement_mint(ctx: Context<ManageSettlementMint>, settlement_mint: Pubkey) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let allowed_settlement_mints = &mut ctx.accounts.config.allowed_settlement_mints;
+
+        require!(
+            !allowed_settlement_mints.contains(&settlement_mint),
+            ErrorCode::SettlementMintAlreadyAllowed
+        );
+        require!(
+            allowed_settlement_mints.len() < MAX_SETTLEMENT_MINTS,
+            ErrorCode::TooManySettlementMints
+        );
+
+        allowed_settlement_mints.push(settlement_mint);
+
+        emit!(SettlementMintAdded {
+            settlement_mint,
+            added_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Only stops new ecosystems from choosing the mint; existing ecosystems keep settling in it.
+    pub fn remove_settlement_mint(ctx: Context<ManageSettlementMint>, settlement_mint: Pubkey) -> Result<()> {
         require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let allowed_settlement_mints = &mut ctx.accounts.config.allowed_settlement_mints;
+
+        let position = allowed_settlement_mints.iter().position(|&x| x == settlement_mint);
+        require!(position.is_some(), ErrorCode::InvalidSettlementMint);
+
+        allowed_settlement_mints.remove(position.unwrap());
+
+        emit!(SettlementMintRemoved {
+            settlement_mint,
+            removed_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_ecosystem(ctx: Context<CreateEcosystem>, args: TokenMetadataArgs) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::EcosystemCreator), ErrorCode::Unauthorized);
     
         let TokenMetadataArgs {
             decimals: _,
-            name: _,
-            symbol: _,
-            uri: _,
+            name,
+            symbol,
+            uri,
             transfer_hook_program_id: _,
             ecosystem_partner_wallet,
             max_minting_cap,
             withdrawal_fee_basis_points,
             deposit_fee_basis_points,
             collateral_token_mint: _,
+            redemption_fee_basis_points,
+            settlement_mint,
         } = args;
         
+        validate_fee_basis_points(
+            deposit_fee_basis_points,
+            withdrawal_fee_basis_points,
+            redemption_fee_basis_points,
+        )?;
+
         require!(
-            deposit_fee_basis_points <= 10000 && withdrawal_fee_basis_points <= 10000,
-            ErrorCode::InvalidFeePercentage
+            ctx.accounts.config.allowed_settlement_mints.contains(&settlement_mint),
+            ErrorCode::InvalidSettlementMint
         );
+
+        let mint_info = ctx.accounts.mint_account.to_account_info();
+        let metadata = TokenMetadata {
+            name: name.clone(),
+            symbol: symbol.clone(),
+            uri: uri.clone(),
+            ..Default::default()
+        };
+        let metadata_len = mint_info.data_len()
+            .checked_add(metadata.tlv_size_of()?)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        fund_realloc(
+            &ctx.accounts.payer.to_account_info(),
+            &mint_info,
+            &ctx.accounts.system_program.to_account_info(),
+            metadata_len,
+        )?;
+
+        invoke(
+            &spl_token_metadata_interface::instruction::initialize(
+                &ctx.accounts.token_program.key(),
+                &mint_info.key(),
+                &ctx.accounts.mint_authority.key(),
+                &mint_info.key(),
+                &ctx.accounts.payer.key(),
+                name,
+                symbol,
+                uri,
+            ),
+            &[
+                mint_info.clone(),
+                ctx.accounts.mint_authority.to_account_info(),
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+            ],
+        )?;
         
         set_authority(
             CpiContext::new(
@@ -129,9 +844,33 @@ pub mod token_deployer {
         ecosystem_config.max_minting_cap = max_minting_cap;
         ecosystem_config.withdrawal_fee_basis_points = withdrawal_fee_basis_points;
         ecosystem_config.deposit_fee_basis_points = deposit_fee_basis_points;
+        ecosystem_config.redemption_fee_basis_points = redemption_fee_basis_points;
         ecosystem_config.collateral_token_mint = ctx.accounts.collateral_token_mint.key();
-        ecosystem_config.ecosystem_freeze = false;
-        ecosystem_config.collected_fees = 0;
+        ecosystem_config.pause_flags = 0;
+        ecosystem_config.collected_collateral_fees = 0;
+        ecosystem_config.collected_settlement_fees = 0;
+        ecosystem_config.pending_fee_update = None;
+        ecosystem_config.approval_threshold = 0;
+        ecosystem_config.high_value_amount = 0;
+        ecosystem_config.high_value_approval_threshold = 0;
+        ecosystem_config.refund_policy = RefundPolicy::UsdcDirect;
+        ecosystem_config.settlement_mint = settlement_mint;
+        ecosystem_config.mint_window_limit = 0;
+        ecosystem_config.mint_window_duration = 0;
+        ecosystem_config.mint_window_updated_at = 0;
+        ecosystem_config.minted_in_window = 0;
+        ecosystem_config.status = EcosystemStatus::Active;
+        ecosystem_config.sunset_ends_at = 0;
+        ecosystem_config.total_merchant_balance = 0;
+        ecosystem_config.price_feed = None;
+        ecosystem_config.max_price_deviation_bps = 0;
+        ecosystem_config.max_price_staleness = 0;
+        ecosystem_config.version = ECOSYSTEM_CONFIG_VERSION;
+        ecosystem_config.legacy_migrated_amount = 0;
+        ecosystem_config.legacy_migrated_input_amount = 0;
+        ecosystem_config.pending_partner_wallet = None;
+        ecosystem_config.partner_epoch = 0;
+        ecosystem_config.price_feed_id = [0; 32];
         
         ecosystem_config.collateral_token_program = ctx.accounts.collateral_token_program.key();
 
@@ -142,6 +881,8 @@ pub mod token_deployer {
             max_minting_cap,
             deposit_fee_bps: deposit_fee_basis_points,
             withdrawal_fee_bps: withdrawal_fee_basis_points,
+            redemption_fee_bps: redemption_fee_basis_points,
+            settlement_mint,
             timestamp: Clock::get()?.unix_timestamp,
         });
 
@@ -151,9 +892,11 @@ pub mod token_deployer {
     pub fn deposit_ecosystem(ctx: Context<DepositEcosystem>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
 
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Deposit)?;
+
         require!(
-            !ctx.accounts.config.global_freeze && !ctx.accounts.ecosystem_config.ecosystem_freeze,
-            ErrorCode::FreezeStateActive
+            ctx.accounts.ecosystem_config.status == EcosystemStatus::Active,
+            ErrorCode::EcosystemNotActive
         );
 
         require!(
@@ -164,7 +907,33 @@ pub mod token_deployer {
             ctx.accounts.to_ata.mint == ctx.accounts.mint.key(),
             ErrorCode::InvalidToken
         );
+
+        if ctx.accounts.payer.key() != ctx.accounts.ecosystem_config.ecosystem_partner_wallet {
+            let Some(depositor) = ctx.accounts.depositor.as_mut() else {
+                return err!(ErrorCode::Unauthorized);
+            };
+
+            require!(
+                depositor.partner_epoch == ctx.accounts.ecosystem_config.partner_epoch,
+                ErrorCode::DepositorRevoked
+            );
+
+            let total_deposited = depositor.total_deposited
+                .checked_add(amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+            if let Some(deposit_allowance) = depositor.deposit_allowance {
+                require!(
+                    total_deposited <= deposit_allowance,
+                    ErrorCode::DepositAllowanceExceeded
+                );
+            }
+
+            depositor.total_deposited = total_deposited;
+        }
         
+        ctx.accounts.ecosystem_config.apply_pending_fee_update(ctx.accounts.mint.key(), Clock::get()?.unix_timestamp);
+
         let current_supply = ctx.accounts.mint.supply;
         let max_minting_cap = ctx.accounts.ecosystem_config.max_minting_cap;
         let deposit_fee_basis_points = ctx.accounts.ecosystem_config.deposit_fee_basis_points;
@@ -195,14 +964,16 @@ pub mod token_deployer {
                 ctx.accounts.collateral_token_mint.decimals,
             )?;
             
-            ctx.accounts.ecosystem_config.collected_fees = ctx.accounts.ecosystem_config.collected_fees
+            ctx.accounts.ecosystem_config.collected_collateral_fees = ctx.accounts.ecosystem_config.collected_collateral_fees
                 .checked_add(fee_amount)
                 .ok_or(ErrorCode::ArithmeticOverflow)?;
                 
-            msg!("Updated collected fees to: {}", ctx.accounts.ecosystem_config.collected_fees);
+            msg!("Updated collected collateral fees to: {}", ctx.accounts.ecosystem_config.collected_collateral_fees);
         }
         
         let remaining_amount = amount.checked_sub(fee_amount).ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        ctx.accounts.ecosystem_config.record_mint(remaining_amount, Clock::get()?.unix_timestamp)?;
         
         if remaining_amount > 0 {
             transfer_checked(
@@ -254,496 +1025,3328 @@ pub mod token_deployer {
             fee: fee_amount,
             timestamp: Clock::get()?.unix_timestamp,
         });
+
+        require_collateral_covers_supply(&mut ctx.accounts.collateral_vault, &mut ctx.accounts.mint)?;
         
         Ok(())
     }
-    
-    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
-        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
-        
-        let collected_fees = ctx.accounts.ecosystem_config.collected_fees;
-        
-        require!(collected_fees > 0, ErrorCode::NoFeesToCollect);
-        
-        transfer_checked(
-            CpiContext::new_with_signer(
-                ctx.accounts.collateral_token_program.to_account_info(),
-                TransferChecked {
-                    from: ctx.accounts.fee_vault.to_account_info(),
-                    to: ctx.accounts.destination_account.to_account_info(),
-                    authority: ctx.accounts.fee_vault_authority.to_account_info(),
-                    mint: ctx.accounts.collateral_token_mint.to_account_info(),
-                },
-                &[&[
-                    b"fee_vault_authority".as_ref(),
-                    ctx.accounts.mint.key().as_ref(),
-                    &[ctx.bumps.fee_vault_authority],
-                ]],
-            ),
-            collected_fees,
-            ctx.accounts.collateral_token_mint.decimals,
+
+    pub fn redeem_ecosystem(ctx: Context<RedeemEcosystem>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Redeem)?;
+
+        // Any holder can redeem during the sunset redemption window, otherwise only the partner
+        let status = ctx.accounts.ecosystem_config.status;
+        require!(status != EcosystemStatus::Closed, ErrorCode::EcosystemClosed);
+        if ctx.accounts.payer.key() != ctx.accounts.ecosystem_config.ecosystem_partner_wallet {
+            require!(status == EcosystemStatus::Sunsetting, ErrorCode::Unauthorized);
+            require!(
+                Clock::get()?.unix_timestamp < ctx.accounts.ecosystem_config.sunset_ends_at,
+                ErrorCode::RedemptionWindowClosed
+            );
+        }
+
+        ctx.accounts.ecosystem_config.apply_pending_fee_update(ctx.accounts.mint.key(), Clock::get()?.unix_timestamp);
+
+        let redemption_fee_basis_points = ctx.accounts.ecosystem_config.redemption_fee_basis_points;
+
+        let fee_amount = amount
+            .checked_mul(redemption_fee_basis_points as u64)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            .checked_div(10000)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        let redeemed_amount = amount.checked_sub(fee_amount).ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.mint.to_account_info(),
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            amount,
         )?;
-        
-        emit!(FeesCollected {
-            ecosystem_mint: ctx.accounts.mint.key(),
-            collector: ctx.accounts.payer.key(),
-            amount: collected_fees,
+
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"fee_vault_authority".as_ref(),
+            mint_key.as_ref(),
+            &[ctx.bumps.fee_vault_authority],
+        ]];
+
+        if fee_amount > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.collateral_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.collateral_vault.to_account_info(),
+                        to: ctx.accounts.fee_vault.to_account_info(),
+                        authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                        mint: ctx.accounts.collateral_token_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                fee_amount,
+                ctx.accounts.collateral_token_mint.decimals,
+            )?;
+
+            ctx.accounts.ecosystem_config.collected_collateral_fees = ctx.accounts.ecosystem_config.collected_collateral_fees
+                .checked_add(fee_amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+        }
+
+        if redeemed_amount > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.collateral_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.collateral_vault.to_account_info(),
+                        to: ctx.accounts.user_collateral_account.to_account_info(),
+                        authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                        mint: ctx.accounts.collateral_token_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                redeemed_amount,
+                ctx.accounts.collateral_token_mint.decimals,
+            )?;
+        }
+
+        emit!(EcosystemRedeemed {
+            ecosystem_mint: mint_key,
+            redeemer: ctx.accounts.payer.key(),
+            amount,
+            fee: fee_amount,
             timestamp: Clock::get()?.unix_timestamp,
         });
-        
-        ctx.accounts.ecosystem_config.collected_fees = 0;
-        
+
         Ok(())
     }
     
-    pub fn toggle_global_freeze(ctx: Context<ToggleGlobalFreeze>) -> Result<()> {
-        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
-        
-        ctx.accounts.config.global_freeze = !ctx.accounts.config.global_freeze;
+    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::FeeCollector), ErrorCode::Unauthorized);
+
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::FeeCollection)?;
         
-        emit!(GlobalFreezeToggled {
-            new_state: ctx.accounts.config.global_freeze,
-            toggled_by: ctx.accounts.payer.key(),
-            timestamp: Clock::get()?.unix_timestamp,
-        });
+        let collected_fees = ctx.accounts.ecosystem_config.collected_collateral_fees;
         
-        Ok(())
-    }
-    
-    pub fn toggle_ecosystem_freeze(ctx: Context<ToggleEcosystemFreeze>) -> Result<()> {
-        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+        require!(collected_fees > 0, ErrorCode::NoFeesToCollect);
         
-        ctx.accounts.ecosystem_config.ecosystem_freeze = !ctx.accounts.ecosystem_config.ecosystem_freeze;
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.fee_vault.to_account_info(),
+                    to: ctx.accounts.destination_account.to_account_info(),
+                    authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                    mint: ctx.accounts.collateral_token_mint.to_account_info(),
+                },
+                &[&[
+                    b"fee_vault_authority".as_ref(),
+                    ctx.accounts.mint.key().as_ref(),
+                    &[ctx.bumps.fee_vault_authority],
+                ]],
+            ),
+            collected_fees,
+            ctx.accounts.collateral_token_mint.decimals,
+        )?;
         
-        emit!(EcosystemFreezeToggled {
+        emit!(FeesCollected {
             ecosystem_mint: ctx.accounts.mint.key(),
-            new_state: ctx.accounts.ecosystem_config.ecosystem_freeze,
-            toggled_by: ctx.accounts.payer.key(),
+            fee_mint: ctx.accounts.collateral_token_mint.key(),
+            collector: ctx.accounts.payer.key(),
+            amount: collected_fees,
             timestamp: Clock::get()?.unix_timestamp,
         });
         
-        Ok(())
-    }
+        ctx.accounts.ecosystem_config.collected_collateral_fees = 0;
 
-    pub fn update_max_cap(ctx: Context<UpdateMaxCap>, new_max_cap: u64) -> Result<()> {
-        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
-        
-        let current_supply = ctx.accounts.mint.supply;
-        
-        require!(
-            new_max_cap >= current_supply,
-            ErrorCode::InvalidMaxCap
-        );
-        
-        let old_cap = ctx.accounts.ecosystem_config.max_minting_cap;
-        ctx.accounts.ecosystem_config.max_minting_cap = new_max_cap;
-        
-        msg!("Updated max minting cap to: {}", new_max_cap);
-        
-        emit!(MaxCapUpdated {
-            ecosystem_mint: ctx.accounts.mint.key(),
-            old_cap,
-            new_cap: new_max_cap,
-            updated_by: ctx.accounts.payer.key(),
-            timestamp: Clock::get()?.unix_timestamp,
-        });
+        require_collateral_covers_supply(&mut ctx.accounts.collateral_vault, &mut ctx.accounts.mint)?;
         
         Ok(())
     }
 
-    pub fn swap(ctx: Context<Swap>, amount: u64,
-        purchase_reference: String, data: Vec<u8>) -> Result<()> {
-        require!(amount > 0, ErrorCode::InvalidAmount);
+    pub fn collect_settlement_fees(ctx: Context<CollectSettlementFees>) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::FeeCollector), ErrorCode::Unauthorized);
 
-        require!(
-            purchase_reference.len() <= 64,
-            ErrorCode::InvalidPurchaseReference
-        );
-        
-        require_keys_eq!(*ctx.accounts.jupiter_program.key, jupiter_program_id());
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::FeeCollection)?;
 
-        require_keys_eq!(
-            ctx.accounts.input_mint.key(),
-            ctx.accounts.ecosystem_config.collateral_token_mint,
-            ErrorCode::InvalidCollateralToken
-        );
-        
         require_keys_eq!(
             ctx.accounts.output_mint.key(),
-            usdc_mint_id(),
+            ctx.accounts.ecosystem_config.settlement_mint,
             ErrorCode::InvalidOutputMint
         );
 
-        let merchant_wallet = ctx.accounts.merchant_wallet.key();
+        let collected_fees = ctx.accounts.ecosystem_config.collected_settlement_fees;
+
+        require!(collected_fees > 0, ErrorCode::NoFeesToCollect);
 
-        msg!("amount: {}", amount);
-        msg!("merchant_wallet: {}", merchant_wallet);
-        msg!("purchase_reference: {}", purchase_reference);
-        
         transfer_checked(
             CpiContext::new_with_signer(
-                ctx.accounts.collateral_token_program.to_account_info(),
+                ctx.accounts.output_mint_program.to_account_info(),
                 TransferChecked {
-                    from: ctx.accounts.collateral_vault.to_account_info(),
-                    to: ctx.accounts.vault_input_token_account.to_account_info(),
+                    from: ctx.accounts.settlement_fee_vault.to_account_info(),
+                    to: ctx.accounts.destination_account.to_account_info(),
                     authority: ctx.accounts.fee_vault_authority.to_account_info(),
-                    mint: ctx.accounts.input_mint.to_account_info(),
+                    mint: ctx.accounts.output_mint.to_account_info(),
                 },
                 &[&[
-                    b"fee_vault_authority", 
+                    b"fee_vault_authority".as_ref(),
                     ctx.accounts.mint.key().as_ref(),
                     &[ctx.bumps.fee_vault_authority],
                 ]],
             ),
-            amount,
-            ctx.accounts.input_mint.decimals,
-        )?;
-        
-        burn(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Burn {
-                    mint: ctx.accounts.mint.to_account_info(),
-                    from: ctx.accounts.user_token_account.to_account_info(),
-                    authority: ctx.accounts.payer.to_account_info(),
-                },
-            ),
-            amount,
+            collected_fees,
+            ctx.accounts.output_mint.decimals,
         )?;
-        
-        let initial_usdc_balance = ctx.accounts.vault_output_token_account.amount;
-        
-        let accounts: Vec<AccountMeta> = ctx
-            .remaining_accounts
-            .iter()
-            .map(|acc| {
-                let is_signer = acc.key == &ctx.accounts.vault.key();
-                AccountMeta {
-                    pubkey: *acc.key,
-                    is_signer,
-                    is_writable: acc.is_writable,
-                }
-            })
-            .collect();
-    
-        let accounts_infos: Vec<AccountInfo> = ctx
-            .remaining_accounts
-            .iter()
-            .map(|acc| AccountInfo { ..acc.clone() })
-            .collect();
-    
-        let signer_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, &[ctx.bumps.vault]]];
+
+        emit!(FeesCollected {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            fee_mint: ctx.accounts.output_mint.key(),
+            collector: ctx.accounts.payer.key(),
+            amount: collected_fees,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        ctx.accounts.ecosystem_config.collected_settlement_fees = 0;
+
+        Ok(())
+    }
     
-        invoke_signed(
-            &Instruction {
-                program_id: ctx.accounts.jupiter_program.key(),
-                accounts,
-                data,
-            },
-            &accounts_infos,
-            signer_seeds,
-        )?;
+    pub fn set_global_pause_flags(ctx: Context<SetGlobalPauseFlags>, pause_flags: u8) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::FreezeGuardian), ErrorCode::Unauthorized);
 
-        ctx.accounts.vault_output_token_account.reload()?;
-        
-        let new_usdc_balance = ctx.accounts.vault_output_token_account.amount;
-        
-        let usdc_received = new_usdc_balance.checked_sub(initial_usdc_balance)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
-        
-        msg!("USDC received from swap: {}", usdc_received);
+        require!(pause_flags & !ALL_PAUSE_FLAGS == 0, ErrorCode::InvalidPauseFlags);
         
-        let merchant_balance = &mut ctx.accounts.merchant_balance;
+        let old_pause_flags = ctx.accounts.config.pause_flags;
+        ctx.accounts.config.pause_flags = pause_flags;
         
-        if merchant_balance.merchant == Pubkey::default() {
-            merchant_balance.merchant = merchant_wallet;
-            merchant_balance.ecosystem_mint = ctx.accounts.mint.key();
-            merchant_balance.balance = 0;
-        }
+        emit!(GlobalPauseFlagsUpdated {
+            old_pause_flags,
+            new_pause_flags: pause_flags,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         
-        merchant_balance.balance = merchant_balance.balance
-            .checked_add(usdc_received)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+    
+    pub fn set_ecosystem_pause_flags(ctx: Context<SetEcosystemPauseFlags>, pause_flags: u8) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::FreezeGuardian), ErrorCode::Unauthorized);
+
+        require!(pause_flags & !ALL_PAUSE_FLAGS == 0, ErrorCode::InvalidPauseFlags);
         
-        msg!("New merchant balance: {}", merchant_balance.balance);
+        let old_pause_flags = ctx.accounts.ecosystem_config.pause_flags;
+        ctx.accounts.ecosystem_config.pause_flags = pause_flags;
         
-        emit!(PurchaseProcessed {
+        emit!(EcosystemPauseFlagsUpdated {
             ecosystem_mint: ctx.accounts.mint.key(),
-            user: ctx.accounts.payer.key(),
-            merchant: merchant_wallet,
-            amount,
-            purchase_reference,
+            old_pause_flags,
+            new_pause_flags: pause_flags,
+            updated_by: ctx.accounts.payer.key(),
             timestamp: Clock::get()?.unix_timestamp,
         });
-    
+        
         Ok(())
     }
 
-    pub fn create_withdrawal_request(ctx: Context<CreateWithdrawalRequest>) -> Result<()> {
-        let merchant_balance = &ctx.accounts.merchant_balance;
+    pub fn begin_sunset(ctx: Context<BeginSunset>, redemption_window: i64) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
 
         require!(
-            merchant_balance.balance > 0, 
-            ErrorCode::NoBalanceToWithdraw
+            ctx.accounts.ecosystem_config.status == EcosystemStatus::Active,
+            ErrorCode::EcosystemNotActive
         );
+        require!(redemption_window > 0, ErrorCode::InvalidRedemptionWindow);
 
-        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
-        
-        if withdrawal_request.merchant != Pubkey::default() && !withdrawal_request.is_approved {
-            return err!(ErrorCode::PendingWithdrawalExists);
-        }
-        
-        withdrawal_request.merchant = ctx.accounts.payer.key();
-        withdrawal_request.ecosystem_mint = ctx.accounts.ecosystem_config.key();
-        withdrawal_request.amount = merchant_balance.balance;
-        withdrawal_request.timestamp = Clock::get()?.unix_timestamp;
-        withdrawal_request.is_approved = false;
-        withdrawal_request.approved_by = None;
-        
-        emit!(WithdrawalRequestCreated {
-            merchant: ctx.accounts.payer.key(),
-            ecosystem_mint: ctx.accounts.ecosystem_config.key(),
-            amount: merchant_balance.balance,
-            timestamp: Clock::get()?.unix_timestamp,
+        let now = Clock::get()?.unix_timestamp;
+        let sunset_ends_at = now
+            .checked_add(redemption_window)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.status = EcosystemStatus::Sunsetting;
+        ecosystem_config.sunset_ends_at = sunset_ends_at;
+
+        emit!(EcosystemSunsetStarted {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            sunset_ends_at,
+            started_by: ctx.accounts.payer.key(),
+            timestamp: now,
         });
-        
+
         Ok(())
     }
 
-    pub fn approve_withdrawal_request(ctx: Context<ApproveWithdrawalRequest>) -> Result<()> {
+    /// Sweeps the collateral and fee vaults and closes them. `ecosystem_config` stays open on purpose,
+    /// merchants still withdraw their balances from the settlement vault through it.
+    pub fn close_ecosystem(ctx: Context<CloseEcosystem>) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
         require!(
-            ctx.accounts.config.approvers.contains(&ctx.accounts.approver.key()),
-            ErrorCode::NotAnApprover
+            ctx.accounts.ecosystem_config.status == EcosystemStatus::Sunsetting,
+            ErrorCode::EcosystemNotSunsetting
         );
 
-        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
-        
+        let now = Clock::get()?.unix_timestamp;
         require!(
-            !withdrawal_request.is_approved,
-            ErrorCode::WithdrawalAlreadyApproved
+            now >= ctx.accounts.ecosystem_config.sunset_ends_at,
+            ErrorCode::RedemptionWindowOpen
         );
 
-        require_keys_eq!(
-            ctx.accounts.output_mint.key(),
-            usdc_mint_id(),
-            ErrorCode::InvalidOutputMint
-        );
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"fee_vault_authority".as_ref(),
+            mint_key.as_ref(),
+            &[ctx.bumps.fee_vault_authority],
+        ]];
 
-        let merchant_balance = &mut ctx.accounts.merchant_balance;
-        
-        require!(
-            merchant_balance.balance >= withdrawal_request.amount,
-            ErrorCode::InsufficientBalance
-        );
-        
-        let withdrawal_fee_basis_points = ctx.accounts.ecosystem_config.withdrawal_fee_basis_points;
-        
-        let fee_amount = withdrawal_request.amount
-            .checked_mul(withdrawal_fee_basis_points as u64)
-            .ok_or(ErrorCode::ArithmeticOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
-        
-        let fee_amount = if withdrawal_fee_basis_points > 0 && fee_amount == 0 {
-            1
-        } else {
-            fee_amount
-        };
-        
-        msg!("Withdrawal fee: {}", fee_amount);
-        
-        let withdraw_amount = withdrawal_request.amount
-            .checked_sub(fee_amount)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
-        
-        if fee_amount > 0 {
+        let collateral_returned = ctx.accounts.collateral_vault.amount;
+        let fees_returned = ctx.accounts.fee_vault.amount;
+        let settlement_fees_returned = ctx.accounts.settlement_fee_vault.amount;
+        // Holders that didn't redeem during the window keep uSP that nothing backs anymore
+        let unbacked_supply = ctx.accounts.mint.supply;
+
+        if collateral_returned > 0 {
             transfer_checked(
                 CpiContext::new_with_signer(
-                    ctx.accounts.output_mint_program.to_account_info(),
+                    ctx.accounts.collateral_token_program.to_account_info(),
                     TransferChecked {
-                        from: ctx.accounts.vault_output_token_account.to_account_info(),
-                        to: ctx.accounts.fee_vault.to_account_info(),
-                        authority: ctx.accounts.vault.to_account_info(),
-                        mint: ctx.accounts.output_mint.to_account_info(),
+                        from: ctx.accounts.collateral_vault.to_account_info(),
+                        to: ctx.accounts.partner_collateral_account.to_account_info(),
+                        authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                        mint: ctx.accounts.collateral_token_mint.to_account_info(),
                     },
-                    &[&[VAULT_SEED, &[ctx.bumps.vault]]],
+                    signer_seeds,
                 ),
-                fee_amount,
-                ctx.accounts.output_mint.decimals,
+                collateral_returned,
+                ctx.accounts.collateral_token_mint.decimals,
             )?;
-            
-            ctx.accounts.ecosystem_config.collected_fees = ctx.accounts.ecosystem_config.collected_fees
-                .checked_add(fee_amount)
-                .ok_or(ErrorCode::ArithmeticOverflow)?;
         }
-        
-        transfer_checked(
-            CpiContext::new_with_signer(
-                ctx.accounts.output_mint_program.to_account_info(),
-                TransferChecked {
-                    from: ctx.accounts.vault_output_token_account.to_account_info(),
-                    to: ctx.accounts.merchant_token_account.to_account_info(),
-                    authority: ctx.accounts.vault.to_account_info(),
-                    mint: ctx.accounts.output_mint.to_account_info(),
+
+        if fees_returned > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.collateral_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.fee_vault.to_account_info(),
+                        to: ctx.accounts.treasury_collateral_account.to_account_info(),
+                        authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                        mint: ctx.accounts.collateral_token_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                fees_returned,
+                ctx.accounts.collateral_token_mint.decimals,
+            )?;
+        }
+
+        if settlement_fees_returned > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.settlement_token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.settlement_fee_vault.to_account_info(),
+                        to: ctx.accounts.treasury_settlement_account.to_account_info(),
+                        authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                        mint: ctx.accounts.settlement_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                settlement_fees_returned,
+                ctx.accounts.settlement_mint.decimals,
+            )?;
+        }
+
+        close_account(CpiContext::new_with_signer(
+            ctx.accounts.settlement_token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.settlement_fee_vault.to_account_info(),
+                destination: ctx.accounts.payer.to_account_info(),
+                authority: ctx.accounts.fee_vault_authority.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+
+        for vault in [
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.fee_vault.to_account_info(),
+        ] {
+            close_account(CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                CloseAccount {
+                    account: vault,
+                    destination: ctx.accounts.payer.to_account_info(),
+                    authority: ctx.accounts.fee_vault_authority.to_account_info(),
                 },
-                &[&[VAULT_SEED, &[ctx.bumps.vault]]],
-            ),
-            withdraw_amount,
-            ctx.accounts.output_mint.decimals,
-        )?;
+                signer_seeds,
+            ))?;
+        }
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.status = EcosystemStatus::Closed;
+        ecosystem_config.collected_collateral_fees = 0;
+        ecosystem_config.collected_settlement_fees = 0;
+
+        emit!(EcosystemClosed {
+            ecosystem_mint: mint_key,
+            collateral_returned,
+            fees_returned,
+            settlement_fees_returned,
+            unbacked_supply,
+            closed_by: ctx.accounts.payer.key(),
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_refund_policy(ctx: Context<UpdateRefundPolicy>, refund_policy: RefundPolicy) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let old_refund_policy = ctx.accounts.ecosystem_config.refund_policy;
+        ctx.accounts.ecosystem_config.refund_policy = refund_policy;
+
+        emit!(RefundPolicyUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            old_refund_policy,
+            new_refund_policy: refund_policy,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_max_cap(ctx: Context<UpdateMaxCap>, new_max_cap: u64) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::CapManager), ErrorCode::Unauthorized);
         
-        merchant_balance.balance = merchant_balance.balance
-            .checked_sub(withdrawal_request.amount)
-            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        let current_supply = ctx.accounts.mint.supply;
+        
+        require!(
+            new_max_cap >= current_supply,
+            ErrorCode::InvalidMaxCap
+        );
         
-        withdrawal_request.is_approved = true;
-        withdrawal_request.approved_by = Some(ctx.accounts.approver.key());
+        let old_cap = ctx.accounts.ecosystem_config.max_minting_cap;
+        ctx.accounts.ecosystem_config.max_minting_cap = new_max_cap;
         
-        emit!(WithdrawalRequestApproved {
-            merchant: withdrawal_request.merchant,
-            ecosystem_mint: withdrawal_request.ecosystem_mint,
-            approved_by: ctx.accounts.approver.key(),
-            amount: withdraw_amount,
-            fee: fee_amount,
+        msg!("Updated max minting cap to: {}", new_max_cap);
+        
+        emit!(MaxCapUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            old_cap,
+            new_cap: new_max_cap,
+            updated_by: ctx.accounts.payer.key(),
             timestamp: Clock::get()?.unix_timestamp,
         });
         
         Ok(())
     }
 
-}
+    pub fn update_mint_rate_limit(
+        ctx: Context<UpdateMintRateLimit>,
+        mint_window_limit: u64,
+        mint_window_duration: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::CapManager), ErrorCode::Unauthorized);
+
+        require!(
+            mint_window_limit == 0 || mint_window_duration > 0,
+            ErrorCode::InvalidMintWindow
+        );
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.mint_window_limit = mint_window_limit;
+        ecosystem_config.mint_window_duration = mint_window_duration;
+
+        msg!("Updated mint rate limit to {} per {} seconds", mint_window_limit, mint_window_duration);
+
+        emit!(MintRateLimitUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            mint_window_limit,
+            mint_window_duration,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_price_oracle(
+        ctx: Context<SetPriceOracle>,
+        price_feed: Option<Pubkey>,
+        price_feed_id: [u8; 32],
+        max_price_deviation_bps: u16,
+        max_price_staleness: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        require!(
+            price_feed.is_none() || (max_price_deviation_bps <= 10000 && max_price_staleness > 0),
+            ErrorCode::InvalidPriceOracleConfig
+        );
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.price_feed = price_feed;
+        ecosystem_config.price_feed_id = price_feed_id;
+        ecosystem_config.max_price_deviation_bps = max_price_deviation_bps;
+        ecosystem_config.max_price_staleness = max_price_staleness;
+
+        emit!(PriceOracleUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            price_feed,
+            price_feed_id,
+            max_price_deviation_bps,
+            max_price_staleness,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_price_feed(ctx: Context<UpdatePriceFeed>, price: i64, exponent: i32) -> Result<()> {
+        require!(ctx.accounts.config.has_role(&ctx.accounts.payer.key(), Role::PriceUpdater), ErrorCode::Unauthorized);
+
+        require!(price > 0, ErrorCode::InvalidPriceFeed);
+        require!(
+            (-MAX_PRICE_EXPONENT..=MAX_PRICE_EXPONENT).contains(&exponent),
+            ErrorCode::InvalidPriceFeed
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+
+        let price_feed = &mut ctx.accounts.price_feed;
+        price_feed.ecosystem_mint = ctx.accounts.mint.key();
+        price_feed.price = price;
+        price_feed.exponent = exponent;
+        price_feed.publish_time = now;
+
+        emit!(PriceFeedUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            price,
+            exponent,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_ecosystem_fees(
+        ctx: Context<UpdateEcosystemFees>,
+        deposit_fee_basis_points: u16,
+        withdrawal_fee_basis_points: u16,
+        redemption_fee_basis_points: u16,
+        effective_at: Option<i64>,
+    ) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        validate_fee_basis_points(
+            deposit_fee_basis_points,
+            withdrawal_fee_basis_points,
+            redemption_fee_basis_points,
+        )?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let mint_key = ctx.accounts.mint.key();
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+
+        ecosystem_config.apply_pending_fee_update(mint_key, now);
+
+        let effective_at = effective_at.unwrap_or(now).max(now);
+
+        let fee_increase = deposit_fee_basis_points > ecosystem_config.deposit_fee_basis_points
+            || withdrawal_fee_basis_points > ecosystem_config.withdrawal_fee_basis_points
+            || redemption_fee_basis_points > ecosystem_config.redemption_fee_basis_points;
+
+        if fee_increase {
+            require!(
+                effective_at >= now.checked_add(FEE_INCREASE_NOTICE).ok_or(ErrorCode::ArithmeticOverflow)?,
+                ErrorCode::FeeIncreaseNoticeTooShort
+            );
+        }
+
+        ecosystem_config.pending_fee_update = Some(PendingFeeUpdate {
+            deposit_fee_basis_points,
+            withdrawal_fee_basis_points,
+            redemption_fee_basis_points,
+            effective_at,
+        });
+
+        msg!("Fee update effective at: {}", effective_at);
+
+        if effective_at > now {
+            emit!(EcosystemFeesScheduled {
+                ecosystem_mint: mint_key,
+                deposit_fee_bps: deposit_fee_basis_points,
+                withdrawal_fee_bps: withdrawal_fee_basis_points,
+                redemption_fee_bps: redemption_fee_basis_points,
+                effective_at,
+                scheduled_by: ctx.accounts.payer.key(),
+                timestamp: now,
+            });
+        }
+
+        // Updates without increases can take effect right away
+        ecosystem_config.apply_pending_fee_update(mint_key, now);
+
+        Ok(())
+    }
+
+    pub fn update_ecosystem_metadata(ctx: Context<UpdateEcosystemMetadata>, args: UpdateTokenMetadataArgs) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let UpdateTokenMetadataArgs {
+            name,
+            symbol,
+            uri,
+            additional_metadata,
+            removed_keys,
+        } = args;
+
+        let mut updates: Vec<(Field, String)> = Vec::new();
+        if let Some(name) = name {
+            updates.push((Field::Name, name));
+        }
+        if let Some(symbol) = symbol {
+            updates.push((Field::Symbol, symbol));
+        }
+        if let Some(uri) = uri {
+            updates.push((Field::Uri, uri));
+        }
+        for MetadataField { key, value } in additional_metadata {
+            updates.push((Field::Key(key), value));
+        }
+
+        require!(
+            !updates.is_empty() || !removed_keys.is_empty(),
+            ErrorCode::NoMetadataChanges
+        );
+
+        let mint_info = ctx.accounts.mint.to_account_info();
+
+        let mut metadata = {
+            let mint_data = mint_info.try_borrow_data()?;
+            let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+            mint_state.get_variable_len_extension::<TokenMetadata>()?
+        };
+        let current_metadata_len = metadata.tlv_size_of()?;
+
+        // Replays the CPIs below in the same order, keys are removed first so the freed space
+        // is reused. Each `update_field` reallocs the mint, so rent has to cover the largest
+        // intermediate size, not only the final one.
+        let mut max_metadata_len = current_metadata_len;
+        for key in removed_keys.iter() {
+            metadata.remove_key(key);
+        }
+        for (field, value) in updates.iter() {
+            metadata.update(field.clone(), value.clone());
+            max_metadata_len = max_metadata_len.max(metadata.tlv_size_of()?);
+        }
+
+        let max_len = mint_info.data_len()
+            .checked_sub(current_metadata_len)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            .checked_add(max_metadata_len)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        fund_realloc(
+            &ctx.accounts.payer.to_account_info(),
+            &mint_info,
+            &ctx.accounts.system_program.to_account_info(),
+            max_len,
+        )?;
+
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"mint_authority".as_ref(),
+            mint_key.as_ref(),
+            &[ctx.bumps.mint_authority],
+        ]];
+        let account_infos = [
+            mint_info.clone(),
+            ctx.accounts.mint_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        ];
+
+        for key in removed_keys {
+            invoke_signed(
+                &spl_token_metadata_interface::instruction::remove_key(
+                    &ctx.accounts.token_program.key(),
+                    &mint_key,
+                    &ctx.accounts.mint_authority.key(),
+                    key,
+                    true,
+                ),
+                &account_infos,
+                signer_seeds,
+            )?;
+        }
+
+        for (field, value) in updates {
+            invoke_signed(
+                &spl_token_metadata_interface::instruction::update_field(
+                    &ctx.accounts.token_program.key(),
+                    &mint_key,
+                    &ctx.accounts.mint_authority.key(),
+                    field,
+                    value,
+                ),
+                &account_infos,
+                signer_seeds,
+            )?;
+        }
+
+        emit!(EcosystemMetadataUpdated {
+            ecosystem_mint: mint_key,
+            name: metadata.name,
+            symbol: metadata.symbol,
+            uri: metadata.uri,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Proposes a new partner wallet, which only takes over once it accepts with `accept_partner_wallet`.
+    pub fn update_partner_wallet(ctx: Context<UpdatePartnerWallet>, new_partner_wallet: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        require!(new_partner_wallet != Pubkey::default(), ErrorCode::InvalidPartnerWallet);
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.pending_partner_wallet = Some(new_partner_wallet);
+
+        emit!(PartnerWalletProposed {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            partner_wallet: ecosystem_config.ecosystem_partner_wallet,
+            pending_partner_wallet: new_partner_wallet,
+            proposed_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Drops a partner wallet proposal nobody is going to accept.
+    pub fn cancel_partner_wallet_proposal(ctx: Context<UpdatePartnerWallet>) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        let pending_partner_wallet = ecosystem_config.pending_partner_wallet
+            .take()
+            .ok_or(ErrorCode::NoPendingPartnerWallet)?;
+
+        emit!(PartnerWalletProposalCancelled {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            pending_partner_wallet,
+            cancelled_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// The proposed partner wallet takes over the ecosystem. Depositors delegated by the previous
+    /// partner wallet stop working until the new partner re-adds them with `add_depositor`, which
+    /// resets their allowance and deposit total.
+    pub fn accept_partner_wallet(ctx: Context<UpdatePartnerWallet>) -> Result<()> {
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        let new_partner_wallet = ecosystem_config.pending_partner_wallet
+            .ok_or(ErrorCode::NoPendingPartnerWallet)?;
+
+        require!(ctx.accounts.payer.key() == new_partner_wallet, ErrorCode::Unauthorized);
+
+        let old_partner_wallet = ecosystem_config.ecosystem_partner_wallet;
+        ecosystem_config.ecosystem_partner_wallet = new_partner_wallet;
+        ecosystem_config.pending_partner_wallet = None;
+        ecosystem_config.partner_epoch = ecosystem_config.partner_epoch
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        emit!(PartnerWalletUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            old_partner_wallet,
+            new_partner_wallet,
+            partner_epoch: ecosystem_config.partner_epoch,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_depositor(ctx: Context<AddDepositor>, deposit_allowance: Option<u64>) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        let depositor = &mut ctx.accounts.depositor;
+        // A depositor from a previous partner epoch is re-added in place, a current one stays as is
+        require!(
+            depositor.wallet == Pubkey::default()
+                || depositor.partner_epoch != ctx.accounts.ecosystem_config.partner_epoch,
+            ErrorCode::DepositorAlreadyActive
+        );
+
+        depositor.wallet = ctx.accounts.depositor_wallet.key();
+        depositor.ecosystem_mint = ctx.accounts.mint.key();
+        depositor.deposit_allowance = deposit_allowance;
+        depositor.total_deposited = 0;
+        depositor.partner_epoch = ctx.accounts.ecosystem_config.partner_epoch;
+        depositor.rent_payer = ctx.accounts.payer.key();
+
+        emit!(DepositorAdded {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            depositor: ctx.accounts.depositor_wallet.key(),
+            deposit_allowance,
+            added_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_depositor(ctx: Context<RemoveDepositor>) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        emit!(DepositorRemoved {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            depositor: ctx.accounts.depositor.wallet,
+            removed_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn register_merchant(
+        ctx: Context<RegisterMerchant>,
+        name: String,
+        category: String,
+        payout_address: Pubkey,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        require!(
+            !name.is_empty() && name.len() <= MAX_MERCHANT_NAME_LEN && category.len() <= MAX_MERCHANT_CATEGORY_LEN,
+            ErrorCode::InvalidMerchantDetails
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+        registered_merchant.wallet = ctx.accounts.merchant_wallet.key();
+        registered_merchant.ecosystem_mint = ctx.accounts.mint.key();
+        registered_merchant.name = name.clone();
+        registered_merchant.category = category.clone();
+        registered_merchant.payout_address = payout_address;
+        registered_merchant.active = true;
+        registered_merchant.balance_frozen = false;
+        registered_merchant.registered_at = now;
+        registered_merchant.instant_settlement = false;
+
+        emit!(MerchantRegistered {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: ctx.accounts.merchant_wallet.key(),
+            name,
+            category,
+            payout_address,
+            registered_by: ctx.accounts.payer.key(),
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_merchant(
+        ctx: Context<ManageMerchant>,
+        name: Option<String>,
+        category: Option<String>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+
+        if let Some(name) = name {
+            require!(
+                !name.is_empty() && name.len() <= MAX_MERCHANT_NAME_LEN,
+                ErrorCode::InvalidMerchantDetails
+            );
+            registered_merchant.name = name;
+        }
+
+        if let Some(category) = category {
+            require!(
+                category.len() <= MAX_MERCHANT_CATEGORY_LEN,
+                ErrorCode::InvalidMerchantDetails
+            );
+            registered_merchant.category = category;
+        }
+
+        emit!(MerchantUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: registered_merchant.wallet,
+            name: registered_merchant.name.clone(),
+            category: registered_merchant.category.clone(),
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Swap settlements and approved withdrawals are paid to the payout address, so only the
+    /// merchant can change it. The program owner can too, to recover a merchant that lost its payout wallet.
+    pub fn update_merchant_payout_address(ctx: Context<ManageMerchant>, payout_address: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.registered_merchant.wallet
+                || ctx.accounts.payer.key() == ctx.accounts.config.owner,
+            ErrorCode::Unauthorized
+        );
+
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+        let old_payout_address = registered_merchant.payout_address;
+        registered_merchant.payout_address = payout_address;
+
+        emit!(MerchantPayoutAddressUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: registered_merchant.wallet,
+            old_payout_address,
+            new_payout_address: payout_address,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_merchant_status(ctx: Context<ManageMerchant>, active: bool, balance_frozen: bool) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+        registered_merchant.active = active;
+        registered_merchant.balance_frozen = balance_frozen;
+
+        emit!(MerchantStatusUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: registered_merchant.wallet,
+            active,
+            balance_frozen,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_merchant_settlement_mode(ctx: Context<ManageMerchant>, instant_settlement: bool) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.ecosystem_config.ecosystem_partner_wallet,
+            ErrorCode::Unauthorized
+        );
+
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+        registered_merchant.instant_settlement = instant_settlement;
+
+        emit!(MerchantSettlementModeUpdated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: registered_merchant.wallet,
+            instant_settlement,
+            updated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn swap(ctx: Context<Swap>, amount: u64,
+        purchase_reference: String, min_settlement_out: u64, data: Vec<u8>) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(min_settlement_out > 0, ErrorCode::InvalidAmount);
+
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Swap)?;
+
+        require!(
+            ctx.accounts.ecosystem_config.status != EcosystemStatus::Closed,
+            ErrorCode::EcosystemClosed
+        );
+
+        require!(
+            purchase_reference.len() <= MAX_PURCHASE_REFERENCE_LEN,
+            ErrorCode::InvalidPurchaseReference
+        );
+        
+        require_keys_eq!(
+            ctx.accounts.input_mint.key(),
+            ctx.accounts.ecosystem_config.collateral_token_mint,
+            ErrorCode::InvalidCollateralToken
+        );
+        
+        require_keys_eq!(
+            ctx.accounts.output_mint.key(),
+            ctx.accounts.ecosystem_config.settlement_mint,
+            ErrorCode::InvalidOutputMint
+        );
+
+        let merchant_wallet = ctx.accounts.merchant_wallet.key();
+
+        msg!("amount: {}", amount);
+        msg!("merchant_wallet: {}", merchant_wallet);
+        msg!("purchase_reference: {}", purchase_reference);
+        
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.collateral_vault.to_account_info(),
+                    to: ctx.accounts.vault_input_token_account.to_account_info(),
+                    authority: ctx.accounts.fee_vault_authority.to_account_info(),
+                    mint: ctx.accounts.input_mint.to_account_info(),
+                },
+                &[&[
+                    b"fee_vault_authority", 
+                    ctx.accounts.mint.key().as_ref(),
+                    &[ctx.bumps.fee_vault_authority],
+                ]],
+            ),
+            amount,
+            ctx.accounts.input_mint.decimals,
+        )?;
+        
+        burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.mint.to_account_info(),
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        
+        let initial_settlement_balance = ctx.accounts.vault_output_token_account.amount;
+    
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, mint_key.as_ref(), &[ctx.bumps.vault]]];
+    
+        invoke_jupiter(
+            ctx.accounts.jupiter_program.key(),
+            ctx.remaining_accounts,
+            ctx.accounts.vault.key(),
+            data,
+            signer_seeds,
+        )?;
+
+        ctx.accounts.vault_output_token_account.reload()?;
+        
+        let new_settlement_balance = ctx.accounts.vault_output_token_account.amount;
+        
+        let settlement_received = new_settlement_balance.checked_sub(initial_settlement_balance)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        
+        msg!("Settlement tokens received from swap: {}", settlement_received);
+
+        require!(
+            settlement_received >= min_settlement_out,
+            ErrorCode::SlippageExceeded
+        );
+
+        if let Some(price_feed_key) = ctx.accounts.ecosystem_config.price_feed {
+            let price_feed = ctx.accounts.price_feed
+                .as_ref()
+                .filter(|x| x.key() == price_feed_key)
+                .ok_or(ErrorCode::InvalidPriceFeed)?;
+
+            check_oracle_price(
+                price_feed,
+                &ctx.accounts.ecosystem_config,
+                &ctx.accounts.mint.key(),
+                amount,
+                ctx.accounts.input_mint.decimals,
+                settlement_received,
+                ctx.accounts.output_mint.decimals,
+            )?;
+        }
+        
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+        
+        if merchant_balance.merchant == Pubkey::default() {
+            merchant_balance.merchant = merchant_wallet;
+            merchant_balance.ecosystem_mint = ctx.accounts.mint.key();
+            merchant_balance.balance = 0;
+            merchant_balance.reserved_balance = 0;
+            merchant_balance.request_count = 0;
+            merchant_balance.version = MERCHANT_BALANCE_VERSION;
+        }
+
+        // A frozen merchant isn't paid out, its proceeds are credited to the frozen balance instead
+        let settle_instantly = ctx.accounts.registered_merchant.instant_settlement
+            && !ctx.accounts.registered_merchant.balance_frozen;
+        let mut settlement_credited = 0;
+        let mut settlement_paid_out = 0;
+
+        if settle_instantly {
+            let (Some(merchant_payout_token_account), Some(settlement_fee_vault)) = (
+                ctx.accounts.merchant_payout_token_account.as_ref(),
+                ctx.accounts.settlement_fee_vault.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingSettlementAccount);
+            };
+
+            ctx.accounts.ecosystem_config.apply_pending_fee_update(ctx.accounts.mint.key(), Clock::get()?.unix_timestamp);
+
+            let fee_amount = withdrawal_fee(settlement_received, ctx.accounts.ecosystem_config.withdrawal_fee_basis_points)?;
+            let settle_amount = settlement_received
+                .checked_sub(fee_amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+            if fee_amount > 0 {
+                transfer_checked(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.output_mint_program.to_account_info(),
+                        TransferChecked {
+                            from: ctx.accounts.vault_output_token_account.to_account_info(),
+                            to: settlement_fee_vault.to_account_info(),
+                            authority: ctx.accounts.vault.to_account_info(),
+                            mint: ctx.accounts.output_mint.to_account_info(),
+                        },
+                        signer_seeds,
+                    ),
+                    fee_amount,
+                    ctx.accounts.output_mint.decimals,
+                )?;
+
+                ctx.accounts.ecosystem_config.collected_settlement_fees = ctx.accounts.ecosystem_config.collected_settlement_fees
+                    .checked_add(fee_amount)
+                    .ok_or(ErrorCode::ArithmeticOverflow)?;
+            }
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.output_mint_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault_output_token_account.to_account_info(),
+                        to: merchant_payout_token_account.to_account_info(),
+                        authority: ctx.accounts.vault.to_account_info(),
+                        mint: ctx.accounts.output_mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                settle_amount,
+                ctx.accounts.output_mint.decimals,
+            )?;
+
+            settlement_paid_out = settle_amount;
+
+            emit!(PurchaseSettled {
+                ecosystem_mint: ctx.accounts.mint.key(),
+                merchant: merchant_wallet,
+                payout_address: ctx.accounts.registered_merchant.payout_address,
+                amount: settle_amount,
+                fee: fee_amount,
+                timestamp: Clock::get()?.unix_timestamp,
+            });
+        } else {
+            merchant_balance.balance = merchant_balance.balance
+                .checked_add(settlement_received)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            ctx.accounts.ecosystem_config.total_merchant_balance = ctx.accounts.ecosystem_config.total_merchant_balance
+                .checked_add(settlement_received)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            
+            settlement_credited = settlement_received;
+
+            msg!("New merchant balance: {}", merchant_balance.balance);
+        }
+
+        let purchase_receipt = &mut ctx.accounts.purchase_receipt;
+        purchase_receipt.merchant = merchant_wallet;
+        purchase_receipt.ecosystem_mint = ctx.accounts.mint.key();
+        purchase_receipt.user = ctx.accounts.payer.key();
+        purchase_receipt.purchase_reference = purchase_reference.clone();
+        purchase_receipt.usp_burned = amount;
+        purchase_receipt.settlement_credited = settlement_credited;
+        purchase_receipt.slot = Clock::get()?.slot;
+        purchase_receipt.status = PurchaseStatus::Completed;
+        purchase_receipt.settlement_paid_out = settlement_paid_out;
+        
+        emit!(PurchaseProcessed {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            user: ctx.accounts.payer.key(),
+            merchant: merchant_wallet,
+            amount,
+            purchase_reference,
+            min_settlement_out,
+            settlement_received,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        require_collateral_covers_supply(&mut ctx.accounts.collateral_vault, &mut ctx.accounts.mint)?;
+        require_settlement_covers_merchant_balances(
+            &mut ctx.accounts.vault_output_token_account,
+            &ctx.accounts.ecosystem_config,
+        )?;
+    
+        Ok(())
+    }
+
+    pub fn refund_purchase(
+        ctx: Context<RefundPurchase>,
+        purchase_reference: String,
+        min_collateral_out: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Refund)?;
+
+        require!(
+            ctx.accounts.purchase_receipt.status == PurchaseStatus::Completed,
+            ErrorCode::PurchaseAlreadyRefunded
+        );
+
+        // Instantly settled proceeds already left the vault for the merchant's payout address,
+        // so there is nothing in the merchant balance to refund from
+        require!(
+            ctx.accounts.purchase_receipt.settlement_paid_out == 0,
+            ErrorCode::SettledPurchaseNotRefundable
+        );
+
+        require_keys_eq!(
+            ctx.accounts.output_mint.key(),
+            ctx.accounts.ecosystem_config.settlement_mint,
+            ErrorCode::InvalidOutputMint
+        );
+
+        require!(
+            !ctx.accounts.registered_merchant.balance_frozen,
+            ErrorCode::MerchantBalanceFrozen
+        );
+
+        let refund_amount = ctx.accounts.purchase_receipt.settlement_credited;
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+
+        require!(
+            merchant_balance.available_balance() >= refund_amount,
+            ErrorCode::InsufficientBalance
+        );
+
+        merchant_balance.balance = merchant_balance.balance
+            .checked_sub(refund_amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        // Only falls short for legacy balances whose funds `migrate_legacy_vault` hasn't moved yet
+        ctx.accounts.ecosystem_config.total_merchant_balance = ctx.accounts.ecosystem_config.total_merchant_balance
+            .checked_sub(refund_amount)
+            .ok_or(ErrorCode::LegacyBalanceNotMigrated)?;
+
+        let mint_key = ctx.accounts.mint.key();
+        let vault_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, mint_key.as_ref(), &[ctx.bumps.vault]]];
+        let refund_policy = ctx.accounts.ecosystem_config.refund_policy;
+
+        let usp_minted = match refund_policy {
+            RefundPolicy::UsdcDirect => {
+                let user_output_token_account = ctx.accounts.user_output_token_account
+                    .as_ref()
+                    .ok_or(ErrorCode::MissingRefundAccount)?;
+
+                transfer_checked(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.output_mint_program.to_account_info(),
+                        TransferChecked {
+                            from: ctx.accounts.vault_output_token_account.to_account_info(),
+                            to: user_output_token_account.to_account_info(),
+                            authority: ctx.accounts.vault.to_account_info(),
+                            mint: ctx.accounts.output_mint.to_account_info(),
+                        },
+                        vault_seeds,
+                    ),
+                    refund_amount,
+                    ctx.accounts.output_mint.decimals,
+                )?;
+
+                0
+            }
+            RefundPolicy::ReMint => {
+                require!(min_collateral_out > 0, ErrorCode::InvalidAmount);
+
+                // Re-minting is a deposit on the user's behalf, so it follows the deposit rules
+                require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Deposit)?;
+                require!(
+                    ctx.accounts.ecosystem_config.status == EcosystemStatus::Active,
+                    ErrorCode::EcosystemNotActive
+                );
+
+                let (
+                    Some(input_mint),
+                    Some(input_mint_program),
+                    Some(vault_input_token_account),
+                    Some(collateral_vault),
+                    Some(mint_authority),
+                    Some(user_token_account),
+                    Some(token_program),
+                    Some(jupiter_program),
+                ) = (
+                    ctx.accounts.input_mint.as_ref(),
+                    ctx.accounts.input_mint_program.as_ref(),
+                    ctx.accounts.vault_input_token_account.as_mut(),
+                    ctx.accounts.collateral_vault.as_ref(),
+                    ctx.accounts.mint_authority.as_ref(),
+                    ctx.accounts.user_token_account.as_ref(),
+                    ctx.accounts.token_program.as_ref(),
+                    ctx.accounts.jupiter_program.as_ref(),
+                ) else {
+                    return err!(ErrorCode::MissingRefundAccount);
+                };
+
+                require_route_accounts(
+                    ctx.remaining_accounts,
+                    ctx.accounts.vault.key(),
+                    ctx.accounts.vault_output_token_account.key(),
+                    vault_input_token_account.key(),
+                )?;
+
+                let initial_collateral_balance = vault_input_token_account.amount;
+                let initial_settlement_balance = ctx.accounts.vault_output_token_account.amount;
+
+                invoke_jupiter(
+                    jupiter_program.key(),
+                    ctx.remaining_accounts,
+                    ctx.accounts.vault.key(),
+                    data,
+                    vault_seeds,
+                )?;
+
+                vault_input_token_account.reload()?;
+                ctx.accounts.vault_output_token_account.reload()?;
+
+                let settlement_spent = initial_settlement_balance
+                    .checked_sub(ctx.accounts.vault_output_token_account.amount)
+                    .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+                // The buy-back must spend exactly what the merchant is refunding
+                require!(settlement_spent == refund_amount, ErrorCode::InvalidRefundSwap);
+
+                let collateral_received = vault_input_token_account.amount
+                    .checked_sub(initial_collateral_balance)
+                    .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+                msg!("Collateral bought back: {}", collateral_received);
+
+                require!(
+                    collateral_received >= min_collateral_out,
+                    ErrorCode::SlippageExceeded
+                );
+
+                // The merchant picks the route and `min_collateral_out`, so the user's floor comes
+                // from the oracle when configured, or else from the uSP the purchase burned
+                if let Some(price_feed_key) = ctx.accounts.ecosystem_config.price_feed {
+                    let price_feed = ctx.accounts.price_feed
+                        .as_ref()
+                        .filter(|x| x.key() == price_feed_key)
+                        .ok_or(ErrorCode::InvalidPriceFeed)?;
+
+                    check_oracle_buy_back(
+                        price_feed,
+                        &ctx.accounts.ecosystem_config,
+                        &ctx.accounts.mint.key(),
+                        settlement_spent,
+                        ctx.accounts.output_mint.decimals,
+                        collateral_received,
+                        input_mint.decimals,
+                    )?;
+                } else {
+                    require!(
+                        collateral_received >= ctx.accounts.purchase_receipt.usp_burned,
+                        ErrorCode::SlippageExceeded
+                    );
+                }
+
+                ctx.accounts.ecosystem_config.record_mint(collateral_received, Clock::get()?.unix_timestamp)?;
+
+                require!(
+                    ctx.accounts.mint.supply
+                        .checked_add(collateral_received)
+                        .ok_or(ErrorCode::ArithmeticOverflow)? <= ctx.accounts.ecosystem_config.max_minting_cap,
+                    ErrorCode::ExceedsMaximumCap
+                );
+
+                transfer_checked(
+                    CpiContext::new_with_signer(
+                        input_mint_program.to_account_info(),
+                        TransferChecked {
+                            from: vault_input_token_account.to_account_info(),
+                            to: collateral_vault.to_account_info(),
+                            authority: ctx.accounts.vault.to_account_info(),
+                            mint: input_mint.to_account_info(),
+                        },
+                        vault_seeds,
+                    ),
+                    collateral_received,
+                    input_mint.decimals,
+                )?;
+
+                mint_to(
+                    CpiContext::new_with_signer(
+                        token_program.to_account_info(),
+                        MintTo {
+                            mint: ctx.accounts.mint.to_account_info(),
+                            to: user_token_account.to_account_info(),
+                            authority: mint_authority.to_account_info(),
+                        },
+                        &[&[
+                            b"mint_authority",
+                            mint_key.as_ref(),
+                            &[ctx.bumps.mint_authority.ok_or(ErrorCode::MissingRefundAccount)?],
+                        ]],
+                    ),
+                    collateral_received,
+                )?;
+
+                collateral_received
+            }
+        };
+
+        let purchase_receipt = &mut ctx.accounts.purchase_receipt;
+        purchase_receipt.status = PurchaseStatus::Refunded;
+
+        emit!(PurchaseRefunded {
+            ecosystem_mint: mint_key,
+            merchant: ctx.accounts.payer.key(),
+            user: purchase_receipt.user,
+            purchase_reference,
+            refund_policy,
+            settlement_refunded: refund_amount,
+            usp_minted,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_withdrawal_request(ctx: Context<CreateWithdrawalRequest>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Withdrawal)?;
+
+        require!(
+            !ctx.accounts.registered_merchant.balance_frozen,
+            ErrorCode::MerchantBalanceFrozen
+        );
+
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+
+        require!(
+            merchant_balance.available_balance() > 0, 
+            ErrorCode::NoBalanceToWithdraw
+        );
+
+        require!(
+            amount <= merchant_balance.available_balance(),
+            ErrorCode::InsufficientBalance
+        );
+
+        let request_index = merchant_balance.request_count;
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        
+        withdrawal_request.merchant = ctx.accounts.payer.key();
+        withdrawal_request.ecosystem_mint = ctx.accounts.ecosystem_config.key();
+        withdrawal_request.amount = amount;
+        withdrawal_request.timestamp = Clock::get()?.unix_timestamp;
+        withdrawal_request.status = WithdrawalStatus::Pending;
+        withdrawal_request.resolved_by = None;
+        withdrawal_request.index = request_index;
+        withdrawal_request.resolved_at = 0;
+        withdrawal_request.reason_code = 0;
+        withdrawal_request.approvals = Vec::new();
+
+        merchant_balance.reserved_balance = merchant_balance.reserved_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        merchant_balance.request_count = request_index
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        
+        emit!(WithdrawalRequestCreated {
+            merchant: ctx.accounts.payer.key(),
+            ecosystem_mint: ctx.accounts.ecosystem_config.key(),
+            request_index,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        
+        Ok(())
+    }
+
+    pub fn approve_withdrawal_request(ctx: Context<ApproveWithdrawalRequest>) -> Result<()> {
+        require!(
+            ctx.accounts.config.approvers.contains(&ctx.accounts.approver.key()),
+            ErrorCode::NotAnApprover
+        );
+
+        require_not_paused(&ctx.accounts.config, &ctx.accounts.ecosystem_config, PauseFlag::Approval)?;
+
+        require!(
+            !ctx.accounts.registered_merchant.balance_frozen,
+            ErrorCode::MerchantBalanceFrozen
+        );
+
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        
+        require!(
+            withdrawal_request.status == WithdrawalStatus::Pending,
+            ErrorCode::WithdrawalRequestNotPending
+        );
+
+        require!(
+            !withdrawal_request.approvals.contains(&ctx.accounts.approver.key()),
+            ErrorCode::DuplicateApproval
+        );
+
+        // Approvals from wallets that have since been removed as approvers no longer count,
+        // pruning them also keeps the list within the space reserved for `MAX_APPROVERS`
+        withdrawal_request.approvals.retain(|x| ctx.accounts.config.approvers.contains(x));
+        withdrawal_request.approvals.push(ctx.accounts.approver.key());
+
+        let approvals = withdrawal_request.approvals.len();
+        let required_approvals = ctx.accounts.ecosystem_config.required_approvals(
+            withdrawal_request.amount,
+            ctx.accounts.config.approval_threshold,
+            ctx.accounts.config.approvers.len(),
+        );
+
+        emit!(WithdrawalApprovalRecorded {
+            merchant: withdrawal_request.merchant,
+            ecosystem_mint: withdrawal_request.ecosystem_mint,
+            request_index: withdrawal_request.index,
+            approver: ctx.accounts.approver.key(),
+            approvals: approvals as u8,
+            required_approvals,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        if approvals < required_approvals as usize {
+            msg!("Approvals: {}/{}", approvals, required_approvals);
+            return Ok(());
+        }
+
+        require_keys_eq!(
+            ctx.accounts.output_mint.key(),
+            ctx.accounts.ecosystem_config.settlement_mint,
+            ErrorCode::InvalidOutputMint
+        );
+
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+        
+        require!(
+            merchant_balance.balance >= withdrawal_request.amount,
+            ErrorCode::InsufficientBalance
+        );
+        
+        ctx.accounts.ecosystem_config.apply_pending_fee_update(ctx.accounts.mint.key(), Clock::get()?.unix_timestamp);
+
+        let withdrawal_fee_basis_points = ctx.accounts.ecosystem_config.withdrawal_fee_basis_points;
+        
+        let fee_amount = withdrawal_fee(withdrawal_request.amount, withdrawal_fee_basis_points)?;
+        
+        msg!("Withdrawal fee: {}", fee_amount);
+
+        let mint_key = ctx.accounts.mint.key();
+        let vault_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, mint_key.as_ref(), &[ctx.bumps.vault]]];
+        
+        let withdraw_amount = withdrawal_request.amount
+            .checked_sub(fee_amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        
+        if fee_amount > 0 {
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.output_mint_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault_output_token_account.to_account_info(),
+                        to: ctx.accounts.settlement_fee_vault.to_account_info(),
+                        authority: ctx.accounts.vault.to_account_info(),
+                        mint: ctx.accounts.output_mint.to_account_info(),
+                    },
+                    vault_seeds,
+                ),
+                fee_amount,
+                ctx.accounts.output_mint.decimals,
+            )?;
+            
+            ctx.accounts.ecosystem_config.collected_settlement_fees = ctx.accounts.ecosystem_config.collected_settlement_fees
+                .checked_add(fee_amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+        }
+        
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.output_mint_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault_output_token_account.to_account_info(),
+                    to: ctx.accounts.merchant_token_account.to_account_info(),
+                    authority: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.output_mint.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            withdraw_amount,
+            ctx.accounts.output_mint.decimals,
+        )?;
+        
+        merchant_balance.balance = merchant_balance.balance
+            .checked_sub(withdrawal_request.amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        // Only falls short for legacy balances whose funds `migrate_legacy_vault` hasn't moved yet
+        ctx.accounts.ecosystem_config.total_merchant_balance = ctx.accounts.ecosystem_config.total_merchant_balance
+            .checked_sub(withdrawal_request.amount)
+            .ok_or(ErrorCode::LegacyBalanceNotMigrated)?;
+        merchant_balance.reserved_balance = merchant_balance.reserved_balance
+            .checked_sub(withdrawal_request.amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        
+        let now = Clock::get()?.unix_timestamp;
+
+        withdrawal_request.status = WithdrawalStatus::Approved;
+        withdrawal_request.resolved_by = Some(ctx.accounts.approver.key());
+        withdrawal_request.resolved_at = now;
+        withdrawal_request.approvals = Vec::new();
+        
+        emit!(WithdrawalRequestApproved {
+            merchant: withdrawal_request.merchant,
+            ecosystem_mint: withdrawal_request.ecosystem_mint,
+            request_index: withdrawal_request.index,
+            approved_by: ctx.accounts.approver.key(),
+            amount: withdraw_amount,
+            fee: fee_amount,
+            timestamp: now,
+        });
+
+        shrink_to_receipt(
+            &ctx.accounts.withdrawal_request.to_account_info(),
+            &ctx.accounts.merchant.to_account_info(),
+        )?;
+
+        require_settlement_covers_merchant_balances(
+            &mut ctx.accounts.vault_output_token_account,
+            &ctx.accounts.ecosystem_config,
+        )?;
+        
+        Ok(())
+    }
+
+    pub fn reject_withdrawal_request(ctx: Context<RejectWithdrawalRequest>, reason_code: u16) -> Result<()> {
+        require!(
+            ctx.accounts.config.approvers.contains(&ctx.accounts.approver.key()),
+            ErrorCode::NotAnApprover
+        );
+
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+
+        require!(
+            withdrawal_request.status == WithdrawalStatus::Pending,
+            ErrorCode::WithdrawalRequestNotPending
+        );
+
+        merchant_balance.reserved_balance = merchant_balance.reserved_balance
+            .checked_sub(withdrawal_request.amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        let now = Clock::get()?.unix_timestamp;
+
+        withdrawal_request.status = WithdrawalStatus::Rejected;
+        withdrawal_request.resolved_by = Some(ctx.accounts.approver.key());
+        withdrawal_request.resolved_at = now;
+        withdrawal_request.reason_code = reason_code;
+        withdrawal_request.approvals = Vec::new();
+
+        emit!(WithdrawalRequestRejected {
+            merchant: withdrawal_request.merchant,
+            ecosystem_mint: withdrawal_request.ecosystem_mint,
+            request_index: withdrawal_request.index,
+            rejected_by: ctx.accounts.approver.key(),
+            amount: withdrawal_request.amount,
+            reason_code,
+            timestamp: now,
+        });
+
+        shrink_to_receipt(
+            &ctx.accounts.withdrawal_request.to_account_info(),
+            &ctx.accounts.merchant.to_account_info(),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn cancel_withdrawal_request(ctx: Context<CancelWithdrawalRequest>) -> Result<()> {
+        let withdrawal_request = &ctx.accounts.withdrawal_request;
+        let merchant_balance = &mut ctx.accounts.merchant_balance;
+
+        require!(
+            withdrawal_request.status == WithdrawalStatus::Pending,
+            ErrorCode::WithdrawalRequestNotPending
+        );
+
+        merchant_balance.reserved_balance = merchant_balance.reserved_balance
+            .checked_sub(withdrawal_request.amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        emit!(WithdrawalRequestCancelled {
+            merchant: withdrawal_request.merchant,
+            ecosystem_mint: withdrawal_request.ecosystem_mint,
+            request_index: withdrawal_request.index,
+            amount: withdrawal_request.amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Closes a withdrawal request left pending from before requests were indexed. Those never
+    /// reserved any balance, so the merchant just gets the rent back and files a new request.
+    pub fn close_legacy_withdrawal_request(ctx: Context<CloseLegacyWithdrawalRequest>) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.merchant.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let legacy_request_info = ctx.accounts.legacy_withdrawal_request.to_account_info();
+
+        let legacy_request = {
+            let data = legacy_request_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == *WithdrawalRequest::DISCRIMINATOR,
+                ErrorCode::InvalidLegacyWithdrawalRequest
+            );
+            LegacyWithdrawalRequest::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::InvalidLegacyWithdrawalRequest)?
+        };
+
+        require_keys_eq!(legacy_request.merchant, ctx.accounts.merchant.key(), ErrorCode::Unauthorized);
+
+        let rent = legacy_request_info.lamports();
+        **legacy_request_info.try_borrow_mut_lamports()? = 0;
+        **ctx.accounts.merchant.try_borrow_mut_lamports()? += rent;
+        legacy_request_info.realloc(0, false)?;
+        legacy_request_info.assign(&system_program::ID);
+
+        emit!(LegacyWithdrawalRequestClosed {
+            merchant: legacy_request.merchant,
+            ecosystem_mint: ctx.accounts.mint.key(),
+            amount: legacy_request.amount,
+            closed_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Moves an ecosystem's share of the shared legacy vault into its own vault. The settlement
+    /// tokens back merchant balances credited before vaults were derived per ecosystem, so they
+    /// count toward `total_merchant_balance`. Collateral left over from routes can be moved along.
+    pub fn migrate_legacy_vault(ctx: Context<MigrateLegacyVault>, amount: u64, input_amount: u64) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+        require!(amount > 0 || input_amount > 0, ErrorCode::InvalidAmount);
+
+        require_keys_eq!(
+            ctx.accounts.output_mint.key(),
+            ctx.accounts.ecosystem_config.settlement_mint,
+            ErrorCode::InvalidOutputMint
+        );
+
+        let legacy_vault_seeds: &[&[&[u8]]] = &[&[VAULT_SEED, &[ctx.bumps.legacy_vault]]];
+
+        if amount > 0 {
+            require!(
+                ctx.accounts.legacy_vault_token_account.amount >= amount,
+                ErrorCode::InsufficientBalance
+            );
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.output_mint_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.legacy_vault_token_account.to_account_info(),
+                        to: ctx.accounts.vault_output_token_account.to_account_info(),
+                        authority: ctx.accounts.legacy_vault.to_account_info(),
+                        mint: ctx.accounts.output_mint.to_account_info(),
+                    },
+                    legacy_vault_seeds,
+                ),
+                amount,
+                ctx.accounts.output_mint.decimals,
+            )?;
+        }
+
+        if input_amount > 0 {
+            let (
+                Some(input_mint),
+                Some(input_mint_program),
+                Some(legacy_vault_input_token_account),
+                Some(vault_input_token_account),
+            ) = (
+                ctx.accounts.input_mint.as_ref(),
+                ctx.accounts.input_mint_program.as_ref(),
+                ctx.accounts.legacy_vault_input_token_account.as_ref(),
+                ctx.accounts.vault_input_token_account.as_ref(),
+            ) else {
+                return err!(ErrorCode::MissingMigrationAccount);
+            };
+
+            require!(
+                legacy_vault_input_token_account.amount >= input_amount,
+                ErrorCode::InsufficientBalance
+            );
+
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    input_mint_program.to_account_info(),
+                    TransferChecked {
+                        from: legacy_vault_input_token_account.to_account_info(),
+                        to: vault_input_token_account.to_account_info(),
+                        authority: ctx.accounts.legacy_vault.to_account_info(),
+                        mint: input_mint.to_account_info(),
+                    },
+                    legacy_vault_seeds,
+                ),
+                input_amount,
+                input_mint.decimals,
+            )?;
+        }
+
+        let ecosystem_config = &mut ctx.accounts.ecosystem_config;
+        ecosystem_config.total_merchant_balance = ecosystem_config.total_merchant_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        ecosystem_config.legacy_migrated_amount = ecosystem_config.legacy_migrated_amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        ecosystem_config.legacy_migrated_input_amount = ecosystem_config.legacy_migrated_input_amount
+            .checked_add(input_amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        emit!(LegacyVaultMigrated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            output_mint: ctx.accounts.output_mint.key(),
+            amount,
+            input_amount,
+            total_migrated_amount: ecosystem_config.legacy_migrated_amount,
+            total_migrated_input_amount: ecosystem_config.legacy_migrated_input_amount,
+            migrated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Grows a config created before the current `Config` layout to `CONFIG_SPACE`. Fields appended
+    /// since then read back as zero, except the allowed settlement mints which are seeded with USDC,
+    /// the only settlement mint before it was configurable, so new ecosystems can still be created.
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let config_info = ctx.accounts.config.to_account_info();
+
+        if config_info.data_len() < CONFIG_SPACE {
+            fund_realloc(
+                &ctx.accounts.payer.to_account_info(),
+                &config_info,
+                &ctx.accounts.system_program.to_account_info(),
+                CONFIG_SPACE,
+            )?;
+            config_info.realloc(CONFIG_SPACE, true)?;
+        }
+
+        let mut config = {
+            let data = config_info.try_borrow_data()?;
+            Config::try_deserialize(&mut &data[..])?
+        };
+
+        require!(ctx.accounts.payer.key() == config.owner, ErrorCode::Unauthorized);
+
+        let old_version = config.version;
+        require!(old_version < CONFIG_VERSION, ErrorCode::ConfigUpToDate);
+
+        if !config.allowed_settlement_mints.contains(&usdc_mint_id()) {
+            require!(
+                config.allowed_settlement_mints.len() < MAX_SETTLEMENT_MINTS,
+                ErrorCode::TooManySettlementMints
+            );
+            config.allowed_settlement_mints.push(usdc_mint_id());
+        }
+        config.version = CONFIG_VERSION;
+
+        let mut data = config_info.try_borrow_mut_data()?;
+        config.try_serialize(&mut &mut data[..])?;
+
+        emit!(ConfigMigrated {
+            old_version,
+            new_version: CONFIG_VERSION,
+            migrated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Grows an ecosystem config created before the current `EcosystemConfig` layout to
+    /// `ECOSYSTEM_CONFIG_SPACE`. Fields appended since then read back as zero, except the
+    /// settlement mint which defaults to USDC, the only settlement mint before it was configurable.
+    pub fn migrate_ecosystem_config(ctx: Context<MigrateEcosystemConfig>) -> Result<()> {
+        require!(ctx.accounts.payer.key() == ctx.accounts.config.owner, ErrorCode::Unauthorized);
+
+        let ecosystem_config_info = ctx.accounts.ecosystem_config.to_account_info();
+
+        if ecosystem_config_info.data_len() < ECOSYSTEM_CONFIG_SPACE {
+            fund_realloc(
+                &ctx.accounts.payer.to_account_info(),
+                &ecosystem_config_info,
+                &ctx.accounts.system_program.to_account_info(),
+                ECOSYSTEM_CONFIG_SPACE,
+            )?;
+            ecosystem_config_info.realloc(ECOSYSTEM_CONFIG_SPACE, true)?;
+        }
+
+        let mut ecosystem_config = {
+            let data = ecosystem_config_info.try_borrow_data()?;
+            EcosystemConfig::try_deserialize(&mut &data[..])?
+        };
+
+        let old_version = ecosystem_config.version;
+        require!(old_version < ECOSYSTEM_CONFIG_VERSION, ErrorCode::EcosystemConfigUpToDate);
+
+        if ecosystem_config.settlement_mint == Pubkey::default() {
+            ecosystem_config.settlement_mint = usdc_mint_id();
+        }
+        ecosystem_config.version = ECOSYSTEM_CONFIG_VERSION;
+
+        let mut data = ecosystem_config_info.try_borrow_mut_data()?;
+        ecosystem_config.try_serialize(&mut &mut data[..])?;
+
+        emit!(EcosystemConfigMigrated {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            old_version,
+            new_version: ECOSYSTEM_CONFIG_VERSION,
+            migrated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Grows a merchant balance created before withdrawal reservations to the current layout.
+    /// Only the layout changes, legacy balances are already counted in `total_merchant_balance`
+    /// by `migrate_legacy_vault`.
+    pub fn migrate_merchant_balance(ctx: Context<MigrateMerchantBalance>) -> Result<()> {
+        require!(
+            ctx.accounts.payer.key() == ctx.accounts.config.owner
+                || ctx.accounts.payer.key() == ctx.accounts.merchant_wallet.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let merchant_balance_info = ctx.accounts.merchant_balance.to_account_info();
+
+        if merchant_balance_info.data_len() < MERCHANT_BALANCE_SPACE {
+            fund_realloc(
+                &ctx.accounts.payer.to_account_info(),
+                &merchant_balance_info,
+                &ctx.accounts.system_program.to_account_info(),
+                MERCHANT_BALANCE_SPACE,
+            )?;
+            merchant_balance_info.realloc(MERCHANT_BALANCE_SPACE, true)?;
+        }
+
+        let mut merchant_balance = {
+            let data = merchant_balance_info.try_borrow_data()?;
+            MerchantBalance::try_deserialize(&mut &data[..])?
+        };
+
+        let old_version = merchant_balance.version;
+        require!(old_version < MERCHANT_BALANCE_VERSION, ErrorCode::MerchantBalanceUpToDate);
+
+        merchant_balance.version = MERCHANT_BALANCE_VERSION;
+
+        let mut data = merchant_balance_info.try_borrow_mut_data()?;
+        merchant_balance.try_serialize(&mut &mut data[..])?;
+
+        emit!(MerchantBalanceMigrated {
+            merchant: merchant_balance.merchant,
+            ecosystem_mint: ctx.accounts.mint.key(),
+            old_version,
+            new_version: MERCHANT_BALANCE_VERSION,
+            migrated_by: ctx.accounts.payer.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Registers a merchant that already has a balance from before the merchant registry, so it can
+    /// keep withdrawing it. The merchant is paid out to its own wallet and starts inactive, the owner or
+    /// ecosystem partner has to activate it before it can receive new purchases.
+    pub fn claim_legacy_merchant(ctx: Context<ClaimLegacyMerchant>, name: String, category: String) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_MERCHANT_NAME_LEN && category.len() <= MAX_MERCHANT_CATEGORY_LEN,
+            ErrorCode::InvalidMerchantDetails
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let merchant_wallet = ctx.accounts.payer.key();
+        let registered_merchant = &mut ctx.accounts.registered_merchant;
+        registered_merchant.wallet = merchant_wallet;
+        registered_merchant.ecosystem_mint = ctx.accounts.mint.key();
+        registered_merchant.name = name.clone();
+        registered_merchant.category = category.clone();
+        registered_merchant.payout_address = merchant_wallet;
+        registered_merchant.active = false;
+        registered_merchant.balance_frozen = false;
+        registered_merchant.registered_at = now;
+        registered_merchant.instant_settlement = false;
+
+        emit!(MerchantRegistered {
+            ecosystem_mint: ctx.accounts.mint.key(),
+            merchant: merchant_wallet,
+            name,
+            category,
+            payout_address: merchant_wallet,
+            registered_by: merchant_wallet,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = CONFIG_SPACE,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeOwner<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageOwnership<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageRole<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageSettlementMint<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageApprover<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetApprovalThreshold<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEcosystemApprovalThreshold<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct GetApprovers<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct GetReserveStatus<'info> {
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    /// Only optional once the ecosystem is closed
+    #[account(
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Only optional once the ecosystem is closed
+    #[account(
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: TokenMetadataArgs)]
+pub struct CreateEcosystem<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = args.decimals,
+        mint::authority = payer,
+        extensions::metadata_pointer::authority = payer,
+        extensions::metadata_pointer::metadata_address = mint_account,
+        extensions::transfer_hook::authority = payer,
+        extensions::transfer_hook::program_id = args.transfer_hook_program_id,
+    )]
+    pub mint_account: InterfaceAccount<'info, Mint>,
+    
+    /// CHECK: This is a PDA used as the mint authority
+    #[account(
+        seeds = [b"mint_authority", mint_account.key().as_ref()],
+        bump,
+    )]
+    pub mint_authority: AccountInfo<'info>,
+    
+    #[account(
+        init,
+        payer = payer,
+        space = ECOSYSTEM_CONFIG_SPACE,
+        seeds = [b"ecosystem_config", mint_account.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    /// CHECK: PDA for fee vault authority
+    #[account(
+        seeds = [b"fee_vault_authority", mint_account.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+    
+    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"fee_vault", mint_account.key().as_ref()],
+        bump,
+        token::mint = collateral_token_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = collateral_token_program,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"collateral_vault", mint_account.key().as_ref()],
+        bump,
+        token::mint = collateral_token_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = collateral_token_program,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = settlement_mint.key() == args.settlement_mint @ ErrorCode::InvalidSettlementMint
+    )]
+    pub settlement_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Created up front so fees can be collected before the first withdrawal is approved
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"settlement_fee_vault", mint_account.key().as_ref()],
+        bump,
+        token::mint = settlement_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = settlement_token_program,
+    )]
+    pub settlement_fee_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+    
+    pub token_program: Program<'info, Token2022>,
+    
+    /// CHECK: This can be either legacy token or Token2022 program
+    #[account(
+        constraint = 
+            collateral_token_program.key() == token_program_id() || 
+            collateral_token_program.key() == token2022_program_id()
+            @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+
+    pub settlement_token_program: Interface<'info, TokenInterface>,
+    
+    pub system_program: Program<'info, System>,
+    
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositEcosystem<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    
+    /// CHECK: This is a PDA used as the mint authority
+    #[account(
+        seeds = [b"mint_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_authority: AccountInfo<'info>,
+    
+    #[account(mut)]
+    pub to_ata: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        mut,
+        constraint = user_collateral_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
+        constraint = user_collateral_account.owner == payer.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_collateral_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token2022>,
+    
+    /// CHECK: Will use the token program saved in ecosystem_config
+    #[account(
+        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
+        @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+
+    /// Only required when the payer is a delegated depositor rather than the ecosystem partner
+    #[account(
+        mut,
+        seeds = [b"depositor", mint.key().as_ref(), payer.key().as_ref()],
+        bump,
+    )]
+    pub depositor: Option<Account<'info, Depositor>>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemEcosystem<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.mint == mint.key() @ ErrorCode::InvalidToken,
+        constraint = user_token_account.owner == payer.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = collateral_token_mint.key() == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken
+    )]
+    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_collateral_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
+        constraint = user_collateral_account.owner == payer.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: This is a PDA that owns the fee and collateral vaults
+    #[account(
+        seeds = [b"fee_vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token2022>,
+
+    /// CHECK: Will use the token program saved in ecosystem_config
+    #[account(
+        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
+        @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    
+    /// CHECK: This is a PDA that owns the fee vault
+    #[account(
+        seeds = [b"fee_vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = destination_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
+        constraint = destination_account.owner == config.owner @ ErrorCode::Unauthorized
+    )]
+    pub destination_account: InterfaceAccount<'info, TokenAccount>,
+    
+    /// CHECK: Will use the token program saved in ecosystem_config
+    #[account(
+        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
+        @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CollectSettlementFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    pub output_mint: InterfaceAccount<'info, Mint>,
+    pub output_mint_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: This is a PDA that owns the settlement fee vault
+    #[account(
+        seeds = [b"fee_vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+
+    /// Ecosystems created before the vault was made up front may not have it yet
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"settlement_fee_vault", mint.key().as_ref()],
+        bump,
+        token::mint = output_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = output_mint_program,
+    )]
+    pub settlement_fee_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = destination_account.mint == output_mint.key() @ ErrorCode::InvalidToken,
+        constraint = destination_account.owner == config.owner @ ErrorCode::Unauthorized
+    )]
+    pub destination_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetGlobalPauseFlags<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEcosystemPauseFlags<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    pub mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMaxCap<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMintRateLimit<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEcosystemFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEcosystemMetadata<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: This is a PDA used as the mint authority and metadata update authority
+    #[account(
+        seeds = [b"mint_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    pub token_program: Program<'info, Token2022>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePartnerWallet<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceOracle<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePriceFeed<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + 32 + 8 + 4 + 8,
+        seeds = [b"price_feed", mint.key().as_ref()],
+        bump,
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddDepositor<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    /// CHECK: This is just the public key of the depositor
+    pub depositor_wallet: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + 32 + 32 + (1 + 8) + 8 + 8 + 32,
+        seeds = [b"depositor", mint.key().as_ref(), depositor_wallet.key().as_ref()],
+        bump,
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveDepositor<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    #[account(
+        mut,
+        close = rent_payer,
+        seeds = [b"depositor", mint.key().as_ref(), depositor.wallet.as_ref()],
+        bump,
+    )]
+    pub depositor: Account<'info, Depositor>,
+
+    /// CHECK: Gets the depositor rent back, must be whoever paid it in `add_depositor`
+    #[account(
+        mut,
+        address = depositor.rent_payer @ ErrorCode::InvalidRentPayer,
+    )]
+    pub rent_payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterMerchant<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    /// CHECK: This is just the public key of the merchant
+    pub merchant_wallet: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = MERCHANT_SPACE,
+        seeds = [b"merchant", mint.key().as_ref(), merchant_wallet.key().as_ref()],
+        bump,
+    )]
+    pub registered_merchant: Account<'info, Merchant>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageMerchant<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"merchant", mint.key().as_ref(), registered_merchant.wallet.as_ref()],
+        bump,
+    )]
+    pub registered_merchant: Account<'info, Merchant>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, purchase_reference: String)]
+pub struct Swap<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub input_mint: InterfaceAccount<'info, Mint>,
+    pub input_mint_program: Interface<'info, TokenInterface>,
+    pub output_mint: InterfaceAccount<'info, Mint>,
+    pub output_mint_program: Interface<'info, TokenInterface>,
+
+    #[account(
+      mut,
+      seeds=[VAULT_SEED, mint.key().as_ref()],
+      bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(
+      mut,
+      associated_token::mint=input_mint,
+      associated_token::authority=vault,
+      associated_token::token_program=input_mint_program,
+    )]
+    pub vault_input_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+      mut,
+      associated_token::mint=output_mint,
+      associated_token::authority=vault,
+      associated_token::token_program=output_mint_program,
+    )]
+    pub vault_output_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Jupiter aggregator, or `mock_jupiter` when built with the `localnet` feature
+    #[account(address = jupiter_program_id() @ ErrorCode::InvalidProgramId, executable)]
+    pub jupiter_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == mint.key() @ ErrorCode::InvalidToken,
+        constraint = user_token_account.owner == payer.key() @ ErrorCode::Unauthorized
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token2022>,
+    
+    /// CHECK: This is a PDA that owns the collateral vault
+    #[account(
+        seeds = [b"fee_vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    /// CHECK: Will use the token program saved in ecosystem_config
+    #[account(
+        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
+        @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = MERCHANT_BALANCE_SPACE,
+        seeds = [b"merchant_balance", merchant_wallet.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub merchant_balance: Account<'info, MerchantBalance>,
+    
+    /// CHECK: This is just the public key of the merchant
+    pub merchant_wallet: AccountInfo<'info>,
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
+    #[account(
+        seeds = [b"merchant", mint.key().as_ref(), merchant_wallet.key().as_ref()],
+        bump,
+        constraint = registered_merchant.active @ ErrorCode::MerchantInactive,
+    )]
+    pub registered_merchant: Account<'info, Merchant>,
+
+    /// Fails to initialize if the merchant was already paid for this purchase reference
     #[account(
         init,
         payer = payer,
-        space = 8 + 32 + 1 + 4 + (32 * 10), 
-        seeds = [b"config"],
+        space = PURCHASE_RECEIPT_SPACE,
+        seeds = [
+            b"purchase_receipt",
+            merchant_wallet.key().as_ref(),
+            hash(purchase_reference.as_bytes()).as_ref(),
+        ],
         bump,
     )]
-    pub config: Account<'info, Config>,
-    
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    pub purchase_receipt: Account<'info, PurchaseReceipt>,
+
+    /// Only required when the merchant uses instant settlement
+    #[account(
+        mut,
+        constraint = merchant_payout_token_account.mint == output_mint.key() @ ErrorCode::InvalidToken,
+        constraint = merchant_payout_token_account.owner == registered_merchant.payout_address @ ErrorCode::Unauthorized
+    )]
+    pub merchant_payout_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Only required when the merchant uses instant settlement
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"settlement_fee_vault", mint.key().as_ref()],
+        bump,
+        token::mint = output_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = output_mint_program,
+    )]
+    pub settlement_fee_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: Only required when the ecosystem has a price oracle, must match `ecosystem_config.price_feed`
+    pub price_feed: Option<UncheckedAccount<'info>>,
     
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ManageApprover<'info> {
+#[instruction(purchase_reference: String)]
+pub struct RefundPurchase<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(mut)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Box<Account<'info, EcosystemConfig>>,
+
+    #[account(
+        mut,
+        seeds = [b"merchant_balance", payer.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub merchant_balance: Account<'info, MerchantBalance>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"purchase_receipt",
+            payer.key().as_ref(),
+            hash(purchase_reference.as_bytes()).as_ref(),
+        ],
+        bump,
+        constraint = purchase_receipt.ecosystem_mint == mint.key() @ ErrorCode::InvalidToken,
+    )]
+    pub purchase_receipt: Account<'info, PurchaseReceipt>,
+
+    #[account(
+        seeds = [b"merchant", mint.key().as_ref(), payer.key().as_ref()],
+        bump,
+    )]
+    pub registered_merchant: Account<'info, Merchant>,
+
+    pub output_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub output_mint_program: Interface<'info, TokenInterface>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = output_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = output_mint_program,
+    )]
+    pub vault_output_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Only required for the `UsdcDirect` refund policy
+    #[account(
+        mut,
+        constraint = user_output_token_account.mint == output_mint.key() @ ErrorCode::InvalidToken,
+        constraint = user_output_token_account.owner == purchase_receipt.user @ ErrorCode::Unauthorized
+    )]
+    pub user_output_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// The accounts below are only required for the `ReMint` refund policy
+    #[account(
+        constraint = input_mint.key() == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken
+    )]
+    pub input_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+
+    #[account(
+        constraint = input_mint_program.key() == ecosystem_config.collateral_token_program @ ErrorCode::InvalidProgramId
+    )]
+    pub input_mint_program: Option<Interface<'info, TokenInterface>>,
+
+    #[account(
+        mut,
+        constraint = vault_input_token_account.mint == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken,
+        constraint = vault_input_token_account.owner == vault.key() @ ErrorCode::Unauthorized
+    )]
+    pub vault_input_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        mut,
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: This is a PDA used as the mint authority
+    #[account(
+        seeds = [b"mint_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub mint_authority: Option<AccountInfo<'info>>,
+
     #[account(
         mut,
+        constraint = user_token_account.mint == mint.key() @ ErrorCode::InvalidToken,
+        constraint = user_token_account.owner == purchase_receipt.user @ ErrorCode::Unauthorized
+    )]
+    pub user_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token2022>>,
+
+    /// CHECK: Jupiter aggregator, or `mock_jupiter` when built with the `localnet` feature
+    #[account(address = jupiter_program_id() @ ErrorCode::InvalidProgramId, executable)]
+    pub jupiter_program: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Only required for `ReMint` when the ecosystem has a price oracle, must match `ecosystem_config.price_feed`
+    pub price_feed: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct BeginSunset<'info> {
+    #[account(
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
-    
-    #[account(mut)]
+
     pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
 }
 
 #[derive(Accounts)]
-pub struct GetApprovers<'info> {
+pub struct CloseEcosystem<'info> {
     #[account(
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    #[account(
+        constraint = collateral_token_mint.key() == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken
+    )]
+    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: This is a PDA that owns the fee and collateral vaults
+    #[account(
+        seeds = [b"fee_vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"fee_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"collateral_vault", mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// Receives the collateral left in the collateral vault
+    #[account(
+        mut,
+        constraint = partner_collateral_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
+        constraint = partner_collateral_account.owner == ecosystem_config.ecosystem_partner_wallet @ ErrorCode::Unauthorized
+    )]
+    pub partner_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Receives the collateral fees left in the fee vault
+    #[account(
+        mut,
+        constraint = treasury_collateral_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
+        constraint = treasury_collateral_account.owner == config.owner @ ErrorCode::Unauthorized
+    )]
+    pub treasury_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = settlement_mint.key() == ecosystem_config.settlement_mint @ ErrorCode::InvalidSettlementMint
+    )]
+    pub settlement_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// Ecosystems created before the vault was made up front may not have it yet
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"settlement_fee_vault", mint.key().as_ref()],
+        bump,
+        token::mint = settlement_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = settlement_token_program,
+    )]
+    pub settlement_fee_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Receives the settlement fees left in the settlement fee vault
+    #[account(
+        mut,
+        constraint = treasury_settlement_account.mint == settlement_mint.key() @ ErrorCode::InvalidToken,
+        constraint = treasury_settlement_account.owner == config.owner @ ErrorCode::Unauthorized
+    )]
+    pub treasury_settlement_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: Will use the token program saved in ecosystem_config
+    #[account(
+        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
+        @ ErrorCode::InvalidProgramId
+    )]
+    pub collateral_token_program: AccountInfo<'info>,
+
+    pub settlement_token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(args: TokenMetadataArgs)]
-pub struct CreateEcosystem<'info> {
+pub struct UpdateRefundPolicy<'info> {
     #[account(
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
-    
+
     #[account(mut)]
     pub payer: Signer<'info>,
-    
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
     #[account(
-        init,
-        payer = payer,
-        mint::decimals = args.decimals,
-        mint::authority = payer,
-        extensions::metadata_pointer::authority = payer,
-        extensions::metadata_pointer::metadata_address = mint_account,
-        extensions::transfer_hook::authority = payer,
-        extensions::transfer_hook::program_id = args.transfer_hook_program_id,
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
+    )]
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CreateWithdrawalRequest<'info> {
+    #[account(
+        mut,
+        constraint = payer.key() == merchant_balance.merchant @ ErrorCode::Unauthorized
     )]
-    pub mint_account: InterfaceAccount<'info, Mint>,
+    pub payer: Signer<'info>,
     
-    /// CHECK: This is a PDA used as the mint authority
     #[account(
-        seeds = [b"mint_authority", mint_account.key().as_ref()],
+        mut,
+        seeds = [b"merchant_balance", payer.key().as_ref(), mint.key().as_ref()],
         bump,
     )]
-    pub mint_authority: AccountInfo<'info>,
+    pub merchant_balance: Account<'info, MerchantBalance>,
     
     #[account(
-        init,
-        payer = payer,
-        space = 8 + 32 + 8 + 2 + 2 + 32 + 8 + 1 + 32,
-        seeds = [b"ecosystem_config", mint_account.key().as_ref()],
+        seeds = [b"config"],
         bump,
     )]
-    pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
-    /// CHECK: PDA for fee vault authority
+    pub config: Account<'info, Config>,
+
     #[account(
-        seeds = [b"fee_vault_authority", mint_account.key().as_ref()],
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
-    pub fee_vault_authority: AccountInfo<'info>,
-    
-    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
     
+    pub mint: InterfaceAccount<'info, Mint>,
+
     #[account(
-        init,
-        payer = payer,
-        seeds = [b"fee_vault", mint_account.key().as_ref()],
+        seeds = [b"merchant", mint.key().as_ref(), payer.key().as_ref()],
         bump,
-        token::mint = collateral_token_mint,
-        token::authority = fee_vault_authority,
-        token::token_program = collateral_token_program,
     )]
-    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    pub registered_merchant: Account<'info, Merchant>,
     
     #[account(
         init,
         payer = payer,
-        seeds = [b"collateral_vault", mint_account.key().as_ref()],
+        space = WITHDRAWAL_REQUEST_SPACE,
+        seeds = [
+            b"withdrawal_request",
+            payer.key().as_ref(),
+            ecosystem_config.key().as_ref(),
+            merchant_balance.request_count.to_le_bytes().as_ref(),
+        ],
         bump,
-        token::mint = collateral_token_mint,
-        token::authority = fee_vault_authority,
-        token::token_program = collateral_token_program,
-    )]
-    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token2022>,
-    
-    /// CHECK: This can be either legacy token or Token2022 program
-    #[account(
-        constraint = 
-            collateral_token_program.key() == token_program_id() || 
-            collateral_token_program.key() == token2022_program_id()
-            @ ErrorCode::InvalidProgramId
     )]
-    pub collateral_token_program: AccountInfo<'info>,
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
     
     pub system_program: Program<'info, System>,
-    
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct DepositEcosystem<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    
+pub struct ApproveWithdrawalRequest<'info> {
     #[account(
         seeds = [b"config"],
         bump,
@@ -751,419 +4354,687 @@ pub struct DepositEcosystem<'info> {
     pub config: Account<'info, Config>,
     
     #[account(mut)]
-    pub mint: InterfaceAccount<'info, Mint>,
+    pub approver: Signer<'info>,
     
-    /// CHECK: This is a PDA used as the mint authority
     #[account(
-        seeds = [b"mint_authority", mint.key().as_ref()],
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            withdrawal_request.merchant.as_ref(),
+            ecosystem_config.key().as_ref(),
+            withdrawal_request.index.to_le_bytes().as_ref(),
+        ],
         bump,
     )]
-    pub mint_authority: AccountInfo<'info>,
-    
-    #[account(mut)]
-    pub to_ata: InterfaceAccount<'info, TokenAccount>,
-    
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
+    /// Receives the rent freed when the request shrinks to a receipt
     #[account(
         mut,
-        seeds = [b"ecosystem_config", mint.key().as_ref()],
-        bump,
-        constraint = ecosystem_config.ecosystem_partner_wallet == payer.key() @ ErrorCode::Unauthorized
+        address = withdrawal_request.merchant @ ErrorCode::Unauthorized
     )]
-    pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
-    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    pub merchant: SystemAccount<'info>,
     
     #[account(
         mut,
-        constraint = user_collateral_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
-        constraint = user_collateral_account.owner == payer.key() @ ErrorCode::Unauthorized
+        seeds = [b"merchant_balance", withdrawal_request.merchant.as_ref(), mint.key().as_ref()], 
+        bump,
     )]
-    pub user_collateral_account: InterfaceAccount<'info, TokenAccount>,
+    pub merchant_balance: Account<'info, MerchantBalance>,
     
     #[account(
         mut,
-        seeds = [b"fee_vault", mint.key().as_ref()],
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
-    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
+    
+    pub mint: InterfaceAccount<'info, Mint>,
+    
+    pub output_mint: InterfaceAccount<'info, Mint>,
+    pub output_mint_program: Interface<'info, TokenInterface>,
     
     #[account(
         mut,
-        seeds = [b"collateral_vault", mint.key().as_ref()],
-        bump,
+        seeds = [VAULT_SEED, mint.key().as_ref()],
+        bump
     )]
-    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token2022>,
+    pub vault: SystemAccount<'info>,
     
-    /// CHECK: Will use the token program saved in ecosystem_config
     #[account(
-        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
-        @ ErrorCode::InvalidProgramId
+        mut,
+        associated_token::mint = output_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = output_mint_program,
     )]
-    pub collateral_token_program: AccountInfo<'info>,
-}
+    pub vault_output_token_account: InterfaceAccount<'info, TokenAccount>,
 
-#[derive(Accounts)]
-pub struct CollectFees<'info> {
     #[account(
-        seeds = [b"config"],
+        seeds = [b"merchant", mint.key().as_ref(), withdrawal_request.merchant.as_ref()],
         bump,
-        constraint = config.owner == payer.key() @ ErrorCode::Unauthorized
     )]
-    pub config: Account<'info, Config>,
-    
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    
-    #[account(mut)]
-    pub mint: InterfaceAccount<'info, Mint>,
+    pub registered_merchant: Box<Account<'info, Merchant>>,
     
+    /// Payouts go to the merchant's registered payout address
     #[account(
         mut,
-        seeds = [b"ecosystem_config", mint.key().as_ref()],
-        bump,
+        constraint = merchant_token_account.mint == output_mint.key() @ ErrorCode::InvalidToken,
+        constraint = merchant_token_account.owner == registered_merchant.payout_address @ ErrorCode::Unauthorized
     )]
-    pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
-    pub collateral_token_mint: InterfaceAccount<'info, Mint>,
+    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
     
-    /// CHECK: This is a PDA that owns the fee vault
+    /// CHECK: This is a PDA that owns the settlement fee vault
     #[account(
         seeds = [b"fee_vault_authority", mint.key().as_ref()],
         bump,
     )]
     pub fee_vault_authority: AccountInfo<'info>,
-    
+
     #[account(
-        mut,
-        seeds = [b"fee_vault", mint.key().as_ref()],
+        init_if_needed,
+        payer = approver,
+        seeds = [b"settlement_fee_vault", mint.key().as_ref()],
         bump,
+        token::mint = output_mint,
+        token::authority = fee_vault_authority,
+        token::token_program = output_mint_program,
     )]
-    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(
-        mut,
-        constraint = destination_account.mint == collateral_token_mint.key() @ ErrorCode::InvalidCollateralToken,
-        constraint = destination_account.owner == payer.key() @ ErrorCode::Unauthorized
-    )]
-    pub destination_account: InterfaceAccount<'info, TokenAccount>,
-    
-    /// CHECK: Will use the token program saved in ecosystem_config
-    #[account(
-        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
-        @ ErrorCode::InvalidProgramId
-    )]
-    pub collateral_token_program: AccountInfo<'info>,
+    pub settlement_fee_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ToggleGlobalFreeze<'info> {
+pub struct RejectWithdrawalRequest<'info> {
     #[account(
-        mut,
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
-    
-    #[account(mut)]
-    pub payer: Signer<'info>,
-}
 
-#[derive(Accounts)]
-pub struct ToggleEcosystemFreeze<'info> {
+    pub approver: Signer<'info>,
+
+    /// Receives the rent freed when the request shrinks to a receipt
     #[account(
-        seeds = [b"config"],
+        mut,
+        address = withdrawal_request.merchant @ ErrorCode::Unauthorized
+    )]
+    pub merchant: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            merchant.key().as_ref(),
+            ecosystem_config.key().as_ref(),
+            withdrawal_request.index.to_le_bytes().as_ref(),
+        ],
         bump,
     )]
-    pub config: Account<'info, Config>,
-    
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
+
     #[account(
         mut,
+        seeds = [b"merchant_balance", merchant.key().as_ref(), mint.key().as_ref()],
+        bump,
+    )]
+    pub merchant_balance: Account<'info, MerchantBalance>,
+
+    #[account(
         seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
     pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
+
     pub mint: InterfaceAccount<'info, Mint>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateMaxCap<'info> {
+pub struct CloseLegacyWithdrawalRequest<'info> {
     #[account(
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
-    
-    #[account(mut)]
+
     pub payer: Signer<'info>,
-    
+
+    /// Gets the rent back, it paid for the request
     #[account(mut)]
+    pub merchant: SystemAccount<'info>,
+
     pub mint: InterfaceAccount<'info, Mint>,
-    
+
     #[account(
-        mut,
         seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
     pub ecosystem_config: Account<'info, EcosystemConfig>,
+
+    /// CHECK: Deserialized by the instruction, legacy requests don't load as `Account<WithdrawalRequest>`
+    #[account(
+        mut,
+        seeds = [b"withdrawal_request", merchant.key().as_ref(), ecosystem_config.key().as_ref()],
+        bump,
+        owner = crate::ID @ ErrorCode::InvalidProgramId,
+    )]
+    pub legacy_withdrawal_request: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct Swap<'info> {
+pub struct CancelWithdrawalRequest<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
-    
-    pub input_mint: InterfaceAccount<'info, Mint>,
-    pub input_mint_program: Interface<'info, TokenInterface>,
-    pub output_mint: InterfaceAccount<'info, Mint>,
-    pub output_mint_program: Interface<'info, TokenInterface>,
 
     #[account(
-      mut,
-      seeds=[VAULT_SEED],
-      bump
+        mut,
+        seeds = [
+            b"withdrawal_request",
+            payer.key().as_ref(),
+            ecosystem_config.key().as_ref(),
+            withdrawal_request.index.to_le_bytes().as_ref(),
+        ],
+        bump,
+        constraint = withdrawal_request.merchant == payer.key() @ ErrorCode::Unauthorized,
+        close = payer,
     )]
-    pub vault: SystemAccount<'info>,
+    pub withdrawal_request: Account<'info, WithdrawalRequest>,
 
     #[account(
-      mut,
-      associated_token::mint=input_mint,
-      associated_token::authority=vault,
-      associated_token::token_program=input_mint_program,
+        mut,
+        seeds = [b"merchant_balance", payer.key().as_ref(), mint.key().as_ref()],
+        bump,
     )]
-    pub vault_input_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub merchant_balance: Account<'info, MerchantBalance>,
 
     #[account(
-      mut,
-      associated_token::mint=output_mint,
-      associated_token::authority=vault,
-      associated_token::token_program=output_mint_program,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        bump,
     )]
-    pub vault_output_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub ecosystem_config: Account<'info, EcosystemConfig>,
 
-    pub jupiter_program: Program<'info, Jupiter>,
+    pub mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLegacyVault<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
 
     #[account(mut)]
+    pub payer: Signer<'info>,
+
     pub mint: InterfaceAccount<'info, Mint>,
-    
+
     #[account(
+        mut,
         seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
-    pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
+    pub ecosystem_config: Box<Account<'info, EcosystemConfig>>,
+
+    pub output_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub output_mint_program: Interface<'info, TokenInterface>,
+
+    /// Shared vault that held every ecosystem's swap proceeds before vaults were derived per ecosystem
+    #[account(
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub legacy_vault: SystemAccount<'info>,
+
     #[account(
         mut,
-        constraint = user_token_account.mint == mint.key() @ ErrorCode::InvalidToken,
-        constraint = user_token_account.owner == payer.key() @ ErrorCode::Unauthorized
+        associated_token::mint = output_mint,
+        associated_token::authority = legacy_vault,
+        associated_token::token_program = output_mint_program,
+    )]
+    pub legacy_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [VAULT_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = output_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = output_mint_program,
     )]
-    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token2022>,
-    
-    /// CHECK: This is a PDA that owns the collateral vault
+    pub vault_output_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The accounts below are only required when moving collateral (`input_amount` > 0)
     #[account(
-        seeds = [b"fee_vault_authority", mint.key().as_ref()],
-        bump,
+        constraint = input_mint.key() == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken
     )]
-    pub fee_vault_authority: AccountInfo<'info>,
-    
+    pub input_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+
     #[account(
-        mut,
-        seeds = [b"collateral_vault", mint.key().as_ref()],
-        bump,
+        constraint = input_mint_program.key() == ecosystem_config.collateral_token_program @ ErrorCode::InvalidProgramId
     )]
-    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    /// CHECK: Will use the token program saved in ecosystem_config
+    pub input_mint_program: Option<Interface<'info, TokenInterface>>,
+
     #[account(
-        constraint = collateral_token_program.key() == ecosystem_config.collateral_token_program
-        @ ErrorCode::InvalidProgramId
+        mut,
+        constraint = legacy_vault_input_token_account.mint == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken,
+        constraint = legacy_vault_input_token_account.owner == legacy_vault.key() @ ErrorCode::Unauthorized
     )]
-    pub collateral_token_program: AccountInfo<'info>,
+    pub legacy_vault_input_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
     #[account(
-        init_if_needed,
-        payer = payer,
-        space = 8 + 32 + 8 + 32,
-        seeds = [b"merchant_balance", merchant_wallet.key().as_ref(), mint.key().as_ref()],
-        bump,
+        mut,
+        constraint = vault_input_token_account.mint == ecosystem_config.collateral_token_mint @ ErrorCode::InvalidCollateralToken,
+        constraint = vault_input_token_account.owner == vault.key() @ ErrorCode::Unauthorized
     )]
-    pub merchant_balance: Account<'info, MerchantBalance>,
-    
-    /// CHECK: This is just the public key of the merchant
-    pub merchant_wallet: AccountInfo<'info>,
-    
+    pub vault_input_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct CreateWithdrawalRequest<'info> {
+pub struct MigrateConfig<'info> {
+    /// CHECK: Deserialized by the instruction after growing it, configs created before
+    /// the current layout may be too short to load as `Account<Config>`
     #[account(
         mut,
-        constraint = payer.key() == merchant_balance.merchant @ ErrorCode::Unauthorized
-    )]
-    pub payer: Signer<'info>,
-    
-    #[account(
-        seeds = [b"merchant_balance", payer.key().as_ref(), mint.key().as_ref()],
+        seeds = [b"config"],
         bump,
+        owner = crate::ID @ ErrorCode::InvalidProgramId,
     )]
-    pub merchant_balance: Account<'info, MerchantBalance>,
-    
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateEcosystemConfig<'info> {
     #[account(
-        seeds = [b"ecosystem_config", mint.key().as_ref()],
+        seeds = [b"config"],
         bump,
     )]
-    pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
     pub mint: InterfaceAccount<'info, Mint>,
-    
+
+    /// CHECK: Deserialized by the instruction after growing it, accounts created before
+    /// the current layout are too short to load as `Account<EcosystemConfig>`
     #[account(
-        init_if_needed,
-        payer = payer,
-        space = 8 + 32 + 32 + 8 + 8 + 1 + 33,
-        seeds = [b"withdrawal_request", payer.key().as_ref(), ecosystem_config.key().as_ref()],
+        mut,
+        seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
+        owner = crate::ID @ ErrorCode::InvalidProgramId,
     )]
-    pub withdrawal_request: Account<'info, WithdrawalRequest>,
-    
+    pub ecosystem_config: UncheckedAccount<'info>,
+
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ApproveWithdrawalRequest<'info> {
+pub struct MigrateMerchantBalance<'info> {
     #[account(
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, Config>,
-    
+
     #[account(mut)]
-    pub approver: Signer<'info>,
-    
-    #[account(
-        mut,
-        seeds = [b"withdrawal_request", withdrawal_request.merchant.as_ref(), ecosystem_config.key().as_ref()],
-        bump,
-        close = approver,
-    )]
-    pub withdrawal_request: Account<'info, WithdrawalRequest>,
-    
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: This is just the public key of the merchant
+    pub merchant_wallet: AccountInfo<'info>,
+
+    /// CHECK: Deserialized by the instruction after growing it, accounts created before
+    /// the current layout are too short to load as `Account<MerchantBalance>`
     #[account(
         mut,
-        seeds = [b"merchant_balance", withdrawal_request.merchant.as_ref(), mint.key().as_ref()], 
+        seeds = [b"merchant_balance", merchant_wallet.key().as_ref(), mint.key().as_ref()],
         bump,
+        owner = crate::ID @ ErrorCode::InvalidProgramId,
     )]
-    pub merchant_balance: Account<'info, MerchantBalance>,
-    
+    pub merchant_balance: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLegacyMerchant<'info> {
+    /// The merchant wallet of the legacy merchant balance
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
     #[account(
-        mut,
         seeds = [b"ecosystem_config", mint.key().as_ref()],
         bump,
     )]
     pub ecosystem_config: Account<'info, EcosystemConfig>,
-    
-    pub mint: InterfaceAccount<'info, Mint>,
-    
-    pub output_mint: InterfaceAccount<'info, Mint>,
-    pub output_mint_program: Interface<'info, TokenInterface>,
-    
-    #[account(
-        mut,
-        seeds = [VAULT_SEED],
-        bump
-    )]
-    pub vault: SystemAccount<'info>,
-    
-    #[account(
-        mut,
-        associated_token::mint = output_mint,
-        associated_token::authority = vault,
-        associated_token::token_program = output_mint_program,
-    )]
-    pub vault_output_token_account: InterfaceAccount<'info, TokenAccount>,
-    
+
+    /// CHECK: Only proves the merchant held a balance before the registry, legacy balances
+    /// may predate the current layout so it isn't deserialized
     #[account(
-        mut,
-        constraint = merchant_token_account.mint == output_mint.key() @ ErrorCode::InvalidToken,
-        constraint = merchant_token_account.owner == withdrawal_request.merchant @ ErrorCode::Unauthorized
+        seeds = [b"merchant_balance", payer.key().as_ref(), mint.key().as_ref()],
+        bump,
+        owner = crate::ID @ ErrorCode::InvalidProgramId,
     )]
-    pub merchant_token_account: InterfaceAccount<'info, TokenAccount>,
-    
+    pub merchant_balance: UncheckedAccount<'info>,
+
     #[account(
-        mut,
-        seeds = [b"fee_vault", mint.key().as_ref()],
+        init,
+        payer = payer,
+        space = MERCHANT_SPACE,
+        seeds = [b"merchant", mint.key().as_ref(), payer.key().as_ref()],
         bump,
     )]
-    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    pub registered_merchant: Account<'info, Merchant>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event]
+pub struct ProgramInitialized {
+    pub owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OwnershipTransferStarted {
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OwnershipTransferred {
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OwnershipTransferCancelled {
+    pub owner: Pubkey,
+    pub cancelled_owner: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RoleGranted {
+    pub member: Pubkey,
+    pub role: Role,
+    pub granted_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RoleRevoked {
+    pub member: Pubkey,
+    pub role: Role,
+    pub revoked_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SettlementMintAdded {
+    pub settlement_mint: Pubkey,
+    pub added_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SettlementMintRemoved {
+    pub settlement_mint: Pubkey,
+    pub removed_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ApproverAdded {
+    pub approver: Pubkey,
+    pub added_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ApproverRemoved {
+    pub approver: Pubkey,
+    pub removed_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ApprovalThresholdUpdated {
+    pub old_approval_threshold: u8,
+    pub new_approval_threshold: u8,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemApprovalThresholdUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub approval_threshold: u8,
+    pub high_value_amount: u64,
+    pub high_value_approval_threshold: u8,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemCreated {
+    pub mint: Pubkey,
+    pub ecosystem_partner: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub max_minting_cap: u64,
+    pub deposit_fee_bps: u16,
+    pub withdrawal_fee_bps: u16,
+    pub redemption_fee_bps: u16,
+    pub settlement_mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemDeposited {
+    pub ecosystem_mint: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemRedeemed {
+    pub ecosystem_mint: Pubkey,
+    pub redeemer: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub ecosystem_mint: Pubkey,
+    pub fee_mint: Pubkey,
+    pub collector: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GlobalPauseFlagsUpdated {
+    pub old_pause_flags: u8,
+    pub new_pause_flags: u8,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemPauseFlagsUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub old_pause_flags: u8,
+    pub new_pause_flags: u8,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PartnerWalletProposed {
+    pub ecosystem_mint: Pubkey,
+    pub partner_wallet: Pubkey,
+    pub pending_partner_wallet: Pubkey,
+    pub proposed_by: Pubkey,
+    pub timestamp: i64,
 }
 
 #[event]
-pub struct ProgramInitialized {
-    pub owner: Pubkey,
+pub struct PartnerWalletProposalCancelled {
+    pub ecosystem_mint: Pubkey,
+    pub pending_partner_wallet: Pubkey,
+    pub cancelled_by: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct ApproverAdded {
-    pub approver: Pubkey,
+pub struct PartnerWalletUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub old_partner_wallet: Pubkey,
+    pub new_partner_wallet: Pubkey,
+    pub partner_epoch: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DepositorAdded {
+    pub ecosystem_mint: Pubkey,
+    pub depositor: Pubkey,
+    pub deposit_allowance: Option<u64>,
     pub added_by: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct ApproverRemoved {
-    pub approver: Pubkey,
+pub struct DepositorRemoved {
+    pub ecosystem_mint: Pubkey,
+    pub depositor: Pubkey,
     pub removed_by: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct EcosystemCreated {
-    pub mint: Pubkey,
-    pub ecosystem_partner: Pubkey,
-    pub collateral_mint: Pubkey,
-    pub max_minting_cap: u64,
-    pub deposit_fee_bps: u16,
-    pub withdrawal_fee_bps: u16,
+pub struct MerchantRegistered {
+    pub ecosystem_mint: Pubkey,
+    pub merchant: Pubkey,
+    pub name: String,
+    pub category: String,
+    pub payout_address: Pubkey,
+    pub registered_by: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct EcosystemDeposited {
+pub struct MerchantUpdated {
     pub ecosystem_mint: Pubkey,
-    pub depositor: Pubkey,
+    pub merchant: Pubkey,
+    pub name: String,
+    pub category: String,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MerchantPayoutAddressUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub merchant: Pubkey,
+    pub old_payout_address: Pubkey,
+    pub new_payout_address: Pubkey,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MerchantSettlementModeUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub merchant: Pubkey,
+    pub instant_settlement: bool,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseSettled {
+    pub ecosystem_mint: Pubkey,
+    pub merchant: Pubkey,
+    pub payout_address: Pubkey,
     pub amount: u64,
     pub fee: u64,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct FeesCollected {
+pub struct MerchantStatusUpdated {
     pub ecosystem_mint: Pubkey,
-    pub collector: Pubkey,
-    pub amount: u64,
+    pub merchant: Pubkey,
+    pub active: bool,
+    pub balance_frozen: bool,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseRefunded {
+    pub ecosystem_mint: Pubkey,
+    pub merchant: Pubkey,
+    pub user: Pubkey,
+    pub purchase_reference: String,
+    pub refund_policy: RefundPolicy,
+    pub settlement_refunded: u64,
+    pub usp_minted: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemSunsetStarted {
+    pub ecosystem_mint: Pubkey,
+    pub sunset_ends_at: i64,
+    pub started_by: Pubkey,
     pub timestamp: i64,
 }
 
+/// `ecosystem_config` outlives the close, merchant withdrawals from the settlement vault still need it
 #[event]
-pub struct GlobalFreezeToggled {
-    pub new_state: bool,
-    pub toggled_by: Pubkey,
+pub struct EcosystemClosed {
+    pub ecosystem_mint: Pubkey,
+    pub collateral_returned: u64,
+    pub fees_returned: u64,
+    pub settlement_fees_returned: u64,
+    /// uSP still outstanding when the collateral went back to the partner
+    pub unbacked_supply: u64,
+    pub closed_by: Pubkey,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct EcosystemFreezeToggled {
+pub struct RefundPolicyUpdated {
     pub ecosystem_mint: Pubkey,
-    pub new_state: bool,
-    pub toggled_by: Pubkey,
+    pub old_refund_policy: RefundPolicy,
+    pub new_refund_policy: RefundPolicy,
+    pub updated_by: Pubkey,
     pub timestamp: i64,
 }
 
@@ -1176,10 +5047,106 @@ pub struct MaxCapUpdated {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct PriceOracleUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub price_feed: Option<Pubkey>,
+    pub price_feed_id: [u8; 32],
+    pub max_price_deviation_bps: u16,
+    pub max_price_staleness: i64,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceFeedUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub price: i64,
+    pub exponent: i32,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MintRateLimitUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub mint_window_limit: u64,
+    pub mint_window_duration: i64,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemFeesScheduled {
+    pub ecosystem_mint: Pubkey,
+    pub deposit_fee_bps: u16,
+    pub withdrawal_fee_bps: u16,
+    pub redemption_fee_bps: u16,
+    pub effective_at: i64,
+    pub scheduled_by: Pubkey,
+    pub timestamp: i64,
+}
+
+/// Emitted once a scheduled fee update takes effect, by whichever instruction first runs after `effective_at`
+#[event]
+pub struct EcosystemFeesUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub old_deposit_fee_bps: u16,
+    pub new_deposit_fee_bps: u16,
+    pub old_withdrawal_fee_bps: u16,
+    pub new_withdrawal_fee_bps: u16,
+    pub old_redemption_fee_bps: u16,
+    pub new_redemption_fee_bps: u16,
+    pub effective_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemMetadataUpdated {
+    pub ecosystem_mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub updated_by: Pubkey,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct WithdrawalRequestCreated {
     pub merchant: Pubkey,
     pub ecosystem_mint: Pubkey,
+    pub request_index: u64,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalApprovalRecorded {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub request_index: u64,
+    pub approver: Pubkey,
+    pub approvals: u8,
+    pub required_approvals: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequestRejected {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub request_index: u64,
+    pub rejected_by: Pubkey,
+    pub amount: u64,
+    pub reason_code: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawalRequestCancelled {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub request_index: u64,
     pub amount: u64,
     pub timestamp: i64,
 }
@@ -1188,12 +5155,72 @@ pub struct WithdrawalRequestCreated {
 pub struct WithdrawalRequestApproved {
     pub merchant: Pubkey,
     pub ecosystem_mint: Pubkey,
+    pub request_index: u64,
     pub approved_by: Pubkey,
     pub amount: u64,
     pub fee: u64,
     pub timestamp: i64,
 }
 
+#[event]
+pub struct ReserveSnapshot {
+    pub ecosystem_mint: Pubkey,
+    pub mint_supply: u64,
+    pub collateral_vault_balance: u64,
+    pub fee_vault_balance: u64,
+    pub merchant_balances_owed: u64,
+    pub collateralization_ratio_bps: Option<u64>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LegacyVaultMigrated {
+    pub ecosystem_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount: u64,
+    pub input_amount: u64,
+    pub total_migrated_amount: u64,
+    pub total_migrated_input_amount: u64,
+    pub migrated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigMigrated {
+    pub old_version: u8,
+    pub new_version: u8,
+    pub migrated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EcosystemConfigMigrated {
+    pub ecosystem_mint: Pubkey,
+    pub old_version: u8,
+    pub new_version: u8,
+    pub migrated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MerchantBalanceMigrated {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub old_version: u8,
+    pub new_version: u8,
+    pub migrated_by: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LegacyWithdrawalRequestClosed {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub amount: u64,
+    pub closed_by: Pubkey,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct PurchaseProcessed {
     pub ecosystem_mint: Pubkey,
@@ -1201,14 +5228,78 @@ pub struct PurchaseProcessed {
     pub merchant: Pubkey,
     pub amount: u64,
     pub purchase_reference: String,
+    pub min_settlement_out: u64,
+    pub settlement_received: u64,
     pub timestamp: i64,
 }
 
 #[account]
 pub struct Config {
     pub owner: Pubkey,
-    pub global_freeze: bool,
+    /// Bitmask of `PauseFlag`s paused for every ecosystem
+    pub pause_flags: u8,
     pub approvers: Vec<Pubkey>,
+    pub pending_owner: Option<Pubkey>,
+    pub role_members: Vec<RoleMember>,
+    /// Approvals needed per withdrawal, and the number of approvers `remove_approver` keeps.
+    /// 0 is a valid threshold that only lifts that floor so the last approver can be removed,
+    /// withdrawals still need one approval (see `EcosystemConfig::required_approvals`)
+    pub approval_threshold: u8,
+    /// Mints new ecosystems can choose as their settlement mint
+    pub allowed_settlement_mints: Vec<Pubkey>,
+    /// Layout version, configs created before versioning read back as 0
+    pub version: u8,
+}
+
+impl Config {
+    /// The owner implicitly holds every role.
+    pub fn has_role(&self, member: &Pubkey, role: Role) -> bool {
+        *member == self.owner
+            || self.role_members
+                .iter()
+                .any(|x| x.member == *member && x.roles & role.bit() != 0)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RoleMember {
+    pub member: Pubkey,
+    pub roles: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    EcosystemCreator,
+    FeeCollector,
+    FreezeGuardian,
+    CapManager,
+    ApproverAdmin,
+    PriceUpdater,
+}
+
+impl Role {
+    pub fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+/// Operations that can be paused during an incident. `Deposit` is bit 0 so
+/// accounts frozen with the former freeze booleans read back as deposits paused.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PauseFlag {
+    Deposit,
+    Redeem,
+    Swap,
+    Refund,
+    Withdrawal,
+    Approval,
+    FeeCollection,
+}
+
+impl PauseFlag {
+    pub fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
 }
 
 #[account]
@@ -1218,9 +5309,166 @@ pub struct EcosystemConfig {
     pub withdrawal_fee_basis_points: u16,
     pub deposit_fee_basis_points: u16,  
     pub collateral_token_mint: Pubkey,
-    pub ecosystem_freeze: bool,
-    pub collected_fees: u64,
+    /// Bitmask of `PauseFlag`s paused for this ecosystem
+    pub pause_flags: u8,
+    pub collected_collateral_fees: u64,
     pub collateral_token_program: Pubkey,
+    pub redemption_fee_basis_points: u16,
+    pub collected_settlement_fees: u64,
+    pub pending_fee_update: Option<PendingFeeUpdate>,
+    pub approval_threshold: u8,
+    pub high_value_amount: u64,
+    pub high_value_approval_threshold: u8,
+    pub refund_policy: RefundPolicy,
+    /// Stablecoin that swaps sell collateral into and merchants are paid in
+    pub settlement_mint: Pubkey,
+    /// Max uSP mintable by deposits within any rolling window, 0 disables the limit
+    pub mint_window_limit: u64,
+    /// Window length in seconds
+    pub mint_window_duration: i64,
+    /// Last time `minted_in_window` was decayed
+    pub mint_window_updated_at: i64,
+    /// Recently minted uSP, decays linearly by `mint_window_limit` per `mint_window_duration`
+    pub minted_in_window: u64,
+    pub status: EcosystemStatus,
+    /// End of the sunset redemption window, after which the ecosystem can be closed
+    pub sunset_ends_at: i64,
+    /// Settlement tokens credited to merchant balances and not yet withdrawn or refunded
+    pub total_merchant_balance: u64,
+    /// Collateral/settlement price account swaps are checked against, `None` to disable
+    pub price_feed: Option<Pubkey>,
+    pub max_price_deviation_bps: u16,
+    /// Max age in seconds of the oracle price accepted by swaps
+    pub max_price_staleness: i64,
+    /// Layout version, accounts below `ECOSYSTEM_CONFIG_VERSION` need `migrate_ecosystem_config`
+    pub version: u8,
+    /// Settlement tokens moved in from the shared legacy vault by `migrate_legacy_vault`
+    pub legacy_migrated_amount: u64,
+    /// Collateral moved in from the shared legacy vault by `migrate_legacy_vault`
+    pub legacy_migrated_input_amount: u64,
+    /// Partner wallet proposed by `update_partner_wallet`, waiting for it to accept
+    pub pending_partner_wallet: Option<Pubkey>,
+    /// Bumped on every partner wallet rotation, depositors added under an older epoch can't deposit
+    pub partner_epoch: u64,
+    /// Pyth feed id a Pyth `price_feed` account has to carry, unused for the program-owned feed
+    pub price_feed_id: [u8; 32],
+}
+
+/// Reserve figures returned by `get_reserve_status`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ReserveStatus {
+    pub mint_supply: u64,
+    pub collateral_vault_balance: u64,
+    pub fee_vault_balance: u64,
+    /// Settlement tokens owed to merchants across all merchant balances of the ecosystem
+    pub merchant_balances_owed: u64,
+    /// Collateral vault balance over uSP supply in basis points (10000 = fully backed)
+    pub collateralization_ratio_bps: Option<u64>,
+}
+
+/// Lifecycle of an ecosystem, which only moves forward.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum EcosystemStatus {
+    /// Deposits, swaps and partner redemptions run normally
+    Active,
+    /// Deposits are stopped and every holder can redeem uSP for collateral until `sunset_ends_at`
+    Sunsetting,
+    /// Leftover collateral and fees were returned and the collateral vaults closed
+    Closed,
+}
+
+/// How `refund_purchase` gives a refunded purchase back to the user.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RefundPolicy {
+    /// Pay the settlement tokens the merchant was credited straight to the user
+    UsdcDirect,
+    /// Buy collateral back with those settlement tokens and re-mint uSP to the user against it
+    ReMint,
+}
+
+impl EcosystemConfig {
+    /// Promotes a scheduled fee update to the active fees once its effective time has passed.
+    pub fn apply_pending_fee_update(&mut self, ecosystem_mint: Pubkey, now: i64) {
+        if let Some(fee_update) = self.pending_fee_update {
+            if now >= fee_update.effective_at {
+                emit!(EcosystemFeesUpdated {
+                    ecosystem_mint,
+                    old_deposit_fee_bps: self.deposit_fee_basis_points,
+                    new_deposit_fee_bps: fee_update.deposit_fee_basis_points,
+                    old_withdrawal_fee_bps: self.withdrawal_fee_basis_points,
+                    new_withdrawal_fee_bps: fee_update.withdrawal_fee_basis_points,
+                    old_redemption_fee_bps: self.redemption_fee_basis_points,
+                    new_redemption_fee_bps: fee_update.redemption_fee_basis_points,
+                    effective_at: fee_update.effective_at,
+                    timestamp: now,
+                });
+
+                self.deposit_fee_basis_points = fee_update.deposit_fee_basis_points;
+                self.withdrawal_fee_basis_points = fee_update.withdrawal_fee_basis_points;
+                self.redemption_fee_basis_points = fee_update.redemption_fee_basis_points;
+                self.pending_fee_update = None;
+            }
+        }
+    }
+
+    /// Counts newly minted uSP against the mint rate limit. The minted amount drains linearly at
+    /// `mint_window_limit` per `mint_window_duration`, so capacity frees up gradually instead of
+    /// all at once at a window boundary.
+    pub fn record_mint(&mut self, amount: u64, now: i64) -> Result<()> {
+        if self.mint_window_limit == 0 {
+            return Ok(());
+        }
+
+        let elapsed = now.saturating_sub(self.mint_window_updated_at).max(0) as u128;
+        let decayed = (self.mint_window_limit as u128)
+            .saturating_mul(elapsed)
+            .checked_div(self.mint_window_duration as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        let remaining = (self.minted_in_window as u128).saturating_sub(decayed) as u64;
+
+        let minted_in_window = remaining
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        require!(
+            minted_in_window <= self.mint_window_limit,
+            ErrorCode::MintRateLimitExceeded
+        );
+
+        self.minted_in_window = minted_in_window;
+        self.mint_window_updated_at = now;
+
+        Ok(())
+    }
+
+    /// Number of distinct approvers needed before a withdrawal of `amount` is paid out.
+    /// Falls back to the global threshold when the ecosystem doesn't override it, and is capped
+    /// at the number of approvers left since `remove_approver` can't revisit every ecosystem.
+    pub fn required_approvals(&self, amount: u64, global_approval_threshold: u8, approvers_count: usize) -> u8 {
+        let approval_threshold = if self.approval_threshold > 0 {
+            self.approval_threshold
+        } else {
+            global_approval_threshold
+        };
+
+        let approval_threshold = if self.high_value_amount > 0 && amount >= self.high_value_amount {
+            approval_threshold.max(self.high_value_approval_threshold)
+        } else {
+            approval_threshold
+        };
+
+        approval_threshold
+            .min(u8::try_from(approvers_count).unwrap_or(u8::MAX))
+            .max(1)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PendingFeeUpdate {
+    pub deposit_fee_basis_points: u16,
+    pub withdrawal_fee_basis_points: u16,
+    pub redemption_fee_basis_points: u16,
+    pub effective_at: i64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -1235,10 +5483,43 @@ pub struct TokenMetadataArgs {
     pub withdrawal_fee_basis_points: u16,
     pub deposit_fee_basis_points: u16,
     pub collateral_token_mint: Pubkey,
+    pub redemption_fee_basis_points: u16,
+    pub settlement_mint: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct MetadataField {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateTokenMetadataArgs {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub uri: Option<String>,
+    pub additional_metadata: Vec<MetadataField>,
+    pub removed_keys: Vec<String>,
 }
 
 #[account]
 pub struct WithdrawalRequest {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+    pub status: WithdrawalStatus,
+    pub resolved_by: Option<Pubkey>,
+    pub index: u64,
+    pub resolved_at: i64,
+    pub reason_code: u16,
+    pub approvals: Vec<Pubkey>,
+}
+
+/// Withdrawal request layout from before requests were indexed, stored at
+/// `["withdrawal_request", merchant, ecosystem config]`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyWithdrawalRequest {
     pub merchant: Pubkey,
     pub ecosystem_mint: Pubkey,
     pub amount: u64,
@@ -1247,11 +5528,94 @@ pub struct WithdrawalRequest {
     pub approved_by: Option<Pubkey>,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
 #[account]
 pub struct MerchantBalance {
     pub merchant: Pubkey,
     pub balance: u64,
     pub ecosystem_mint: Pubkey,
+    /// Amount reserved by pending withdrawal requests
+    pub reserved_balance: u64,
+    /// Index of the next withdrawal request
+    pub request_count: u64,
+    /// Layout version, accounts below `MERCHANT_BALANCE_VERSION` need `migrate_merchant_balance`
+    pub version: u8,
+}
+
+impl MerchantBalance {
+    /// Balance that isn't already reserved by pending withdrawal requests.
+    pub fn available_balance(&self) -> u64 {
+        self.balance.saturating_sub(self.reserved_balance)
+    }
+}
+
+/// Price of the collateral token in settlement tokens pushed by a price updater,
+/// for ecosystems whose collateral has no Pyth feed. Price is `price * 10^exponent`.
+#[account]
+pub struct PriceFeed {
+    pub ecosystem_mint: Pubkey,
+    pub price: i64,
+    pub exponent: i32,
+    pub publish_time: i64,
+}
+
+/// Wallet the ecosystem partner lets deposit collateral and mint uSP on its behalf.
+#[account]
+pub struct Depositor {
+    pub wallet: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    /// Max collateral this depositor can deposit in total, `None` for no limit
+    pub deposit_allowance: Option<u64>,
+    pub total_deposited: u64,
+    /// `EcosystemConfig::partner_epoch` when added, stale once the partner wallet rotates
+    pub partner_epoch: u64,
+    /// Last `add_depositor` payer, gets the account rent back from `remove_depositor`
+    pub rent_payer: Pubkey,
+}
+
+#[account]
+pub struct Merchant {
+    pub wallet: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub name: String,
+    pub category: String,
+    pub payout_address: Pubkey,
+    /// Suspended merchants can't receive new purchases
+    pub active: bool,
+    /// Blocks withdrawals and refunds from the merchant balance, e.g. during an investigation
+    pub balance_frozen: bool,
+    pub registered_at: i64,
+    /// Pays swap proceeds, net of the withdrawal fee, straight to the payout address
+    /// instead of crediting the merchant balance
+    pub instant_settlement: bool,
+}
+
+#[account]
+pub struct PurchaseReceipt {
+    pub merchant: Pubkey,
+    pub ecosystem_mint: Pubkey,
+    pub user: Pubkey,
+    pub purchase_reference: String,
+    pub usp_burned: u64,
+    /// Settlement tokens credited to the merchant balance, 0 when the purchase was settled instantly
+    pub settlement_credited: u64,
+    pub slot: u64,
+    pub status: PurchaseStatus,
+    /// Settlement tokens paid straight to the merchant's payout address by instant settlement, net of the
+    /// withdrawal fee
+    pub settlement_paid_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PurchaseStatus {
+    Completed,
+    Refunded,
 }
 
 #[error_code]
@@ -1268,7 +5632,7 @@ pub enum ErrorCode {
     ArithmeticOverflow,
     #[msg("Invalid fee percentage - has to be <= 10000 (100%)")]
     InvalidFeePercentage,
-    #[msg("Operation not allowed: freeze state is active")]
+    #[msg("Operation not allowed: it is paused")]
     FreezeStateActive,
     #[msg("No fees available to collect")]
     NoFeesToCollect,
@@ -1278,7 +5642,7 @@ pub enum ErrorCode {
     InvalidProgramId,
     #[msg("Invalid purchase reference string")]
     InvalidPurchaseReference,
-    #[msg("Output mint must be USDC")]
+    #[msg("Output mint must be the ecosystem settlement mint")]
     InvalidOutputMint,
     #[msg("No balance to withdraw")]
     NoBalanceToWithdraw,
@@ -1296,4 +5660,106 @@ pub enum ErrorCode {
     InsufficientBalance,
     #[msg("Pending withdrawal request already exists")]
     PendingWithdrawalExists,
+    #[msg("Swap output is below the minimum settlement amount")]
+    SlippageExceeded,
+    #[msg("No metadata changes provided")]
+    NoMetadataChanges,
+    #[msg("No pending ownership transfer")]
+    NoPendingOwnershipTransfer,
+    #[msg("Role already granted")]
+    RoleAlreadyGranted,
+    #[msg("Role not granted")]
+    RoleNotGranted,
+    #[msg("Maximum number of role members reached")]
+    TooManyRoleMembers,
+    #[msg("Maximum number of approvers reached")]
+    TooManyApprovers,
+    #[msg("Invalid approval threshold")]
+    InvalidApprovalThreshold,
+    #[msg("Approver has already approved this withdrawal request")]
+    DuplicateApproval,
+    #[msg("Withdrawal request is not pending")]
+    WithdrawalRequestNotPending,
+    #[msg("Purchase has already been refunded")]
+    PurchaseAlreadyRefunded,
+    #[msg("Missing account required by the ecosystem refund policy")]
+    MissingRefundAccount,
+    #[msg("Refund swap must spend exactly the refunded settlement amount")]
+    InvalidRefundSwap,
+    #[msg("Invalid merchant name or category")]
+    InvalidMerchantDetails,
+    #[msg("Merchant is suspended")]
+    MerchantInactive,
+    #[msg("Merchant balance is frozen")]
+    MerchantBalanceFrozen,
+    #[msg("Missing payout or fee account required for instant settlement")]
+    MissingSettlementAccount,
+    #[msg("Settlement mint is not allowed")]
+    InvalidSettlementMint,
+    #[msg("Settlement mint is already allowed")]
+    SettlementMintAlreadyAllowed,
+    #[msg("Maximum number of settlement mints reached")]
+    TooManySettlementMints,
+    #[msg("Deposit exceeds the ecosystem mint rate limit for the current window")]
+    MintRateLimitExceeded,
+    #[msg("Mint window duration must be positive when a mint limit is set")]
+    InvalidMintWindow,
+    #[msg("Invalid ecosystem partner wallet")]
+    InvalidPartnerWallet,
+    #[msg("Deposit exceeds the depositor allowance")]
+    DepositAllowanceExceeded,
+    #[msg("Pause flags contain unknown bits")]
+    InvalidPauseFlags,
+    #[msg("Ecosystem is not active")]
+    EcosystemNotActive,
+    #[msg("Ecosystem is closed")]
+    EcosystemClosed,
+    #[msg("Ecosystem is not sunsetting")]
+    EcosystemNotSunsetting,
+    #[msg("Redemption window must be positive")]
+    InvalidRedemptionWindow,
+    #[msg("Sunset redemption window is still open")]
+    RedemptionWindowOpen,
+    #[msg("Solvency invariant violated")]
+    InvariantViolation,
+    #[msg("Price feed account is missing or invalid")]
+    InvalidPriceFeed,
+    #[msg("Price feed is stale")]
+    StalePriceFeed,
+    #[msg("Swap price deviates too far below the oracle price")]
+    PriceDeviationExceeded,
+    #[msg("Invalid price oracle configuration")]
+    InvalidPriceOracleConfig,
+    #[msg("Ecosystem config is already at the current version")]
+    EcosystemConfigUpToDate,
+    #[msg("Missing collateral account required to migrate the legacy vault")]
+    MissingMigrationAccount,
+    #[msg("Fee increases must be scheduled at least FEE_INCREASE_NOTICE seconds ahead")]
+    FeeIncreaseNoticeTooShort,
+    #[msg("Merchant balance is already at the current version")]
+    MerchantBalanceUpToDate,
+    #[msg("Instantly settled purchases are paid out already and can't be refunded from the merchant balance")]
+    SettledPurchaseNotRefundable,
+    #[msg("Config is already at the current version")]
+    ConfigUpToDate,
+    #[msg("No partner wallet is waiting to be accepted")]
+    NoPendingPartnerWallet,
+    #[msg("Depositor was added by a previous partner wallet")]
+    DepositorRevoked,
+    #[msg("Rent payer doesn't match the account that paid the rent")]
+    InvalidRentPayer,
+    #[msg("Sunset redemption window has ended")]
+    RedemptionWindowClosed,
+    #[msg("Merchant balance predates total_merchant_balance, migrate_legacy_vault has to move its funds first")]
+    LegacyBalanceNotMigrated,
+    #[msg("Vault accounts are required until the ecosystem is closed")]
+    MissingVaultAccount,
+    #[msg("Pyth price update is for a different feed than the ecosystem's price_feed_id")]
+    PriceFeedIdMismatch,
+    #[msg("Oracle price confidence interval is wider than the max price deviation")]
+    PriceConfidenceTooWide,
+    #[msg("Account isn't a legacy withdrawal request")]
+    InvalidLegacyWithdrawalRequest,
+    #[msg("Depositor is already active for the current partner wallet")]
+    DepositorAlreadyActive,
 }
\ No newline at end of file
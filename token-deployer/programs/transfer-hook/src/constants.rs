@@ -9,8 +9,14 @@ pub const FEES_SEED: &[u8] = b"fees";
 #[constant]
 pub const USDC_SEED: &[u8] = b"usdc";
 
-// Mainnet - EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v
-// Devnet - 4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU
+// Selected by the `devnet` or `localnet` cargo feature, mainnet without either.
+// Localnet uses the mainnet address, loaded from a fixture by the test validator
+#[cfg(all(feature = "devnet", feature = "localnet"))]
+compile_error!("The `devnet` and `localnet` features select different clusters, enable at most one");
+#[cfg(feature = "devnet")]
+#[constant]
+pub const USDC_MINT_ADDRESS: Pubkey = pubkey!("4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU");
+#[cfg(not(feature = "devnet"))]
 #[constant]
 pub const USDC_MINT_ADDRESS: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
 
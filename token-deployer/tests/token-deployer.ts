@@ -2,18 +2,27 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { TokenDeployer } from "../target/types/token_deployer";
 import { TransferHook } from "../target/types/transfer_hook";
+import { MockJupiter } from "../target/types/mock_jupiter";
 import { assert } from "chai";
 import {
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  AuthorityType,
+  ExtensionType,
   TOKEN_2022_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
   createAssociatedTokenAccountInstruction,
   createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
   createMintToInstruction,
+  createSetAuthorityInstruction,
   createTransferCheckedWithTransferHookInstruction,
   getAssociatedTokenAddressSync,
   getMint,
+  getMintLen,
+  getTokenMetadata,
 } from "@solana/spl-token";
 import {
+  ComputeBudgetProgram,
   Keypair,
   PublicKey,
   SystemProgram,
@@ -21,6 +30,22 @@ import {
   sendAndConfirmTransaction,
   LAMPORTS_PER_SOL,
 } from "@solana/web3.js";
+import { createHash } from "crypto";
+
+const USDC_MINT = new PublicKey("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+
+// Pyth price update fixtures for the "collateral/usd" feed, published at unix time 0
+const PYTH_PRICE_UPDATE = new PublicKey("8xBVKHWvU5WdSeaFBWXaHZJxjqzLe7Q5oyFpTGqsRAtW");
+const PYTH_PRICE_UPDATE_WIDE_CONF = new PublicKey("R3E4ixz3HGvFrRfv4XFkK8oxsqr1nzrdA6Ua4SqeWrV");
+const PYTH_FEED_ID = [...createHash("sha256").update("collateral/usd").digest()];
+
+// The USDC fixture is a legacy SPL Token mint like real USDC, every other test mint is Token-2022
+const tokenProgramFor = (mint) => (mint.equals(USDC_MINT) ? TOKEN_PROGRAM_ID : TOKEN_2022_PROGRAM_ID);
+
+// Bits of the `PauseFlag` enum
+const PAUSE_DEPOSIT = 1 << 0;
+const PAUSE_SWAP = 1 << 2;
+const PAUSE_WITHDRAWAL = 1 << 4;
 
 describe("token-deployer with transfer hook", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -30,13 +55,14 @@ describe("token-deployer with transfer hook", () => {
 
   const tokenDeployerProgram = anchor.workspace.TokenDeployer as Program<TokenDeployer>;
   const transferHookProgram = anchor.workspace.TransferHook as Program<TransferHook>;
+  const mockJupiterProgram = anchor.workspace.MockJupiter as Program<MockJupiter>;
 
   let mintKeypair, recipient;
   let sourceTokenAccount, destinationTokenAccount, ecosystemPartnerTokenAccount, unauthorizedTokenAccount;
   let decimals, transferAmount;
   let extraAccountMetas;
   
-  let configPda, mintAuthorityPda, ecosystemConfigPda, feeVaultAuthorityPda, feeVaultPda, collateralVaultPda;
+  let configPda, mintAuthorityPda, ecosystemConfigPda, feeVaultAuthorityPda, feeVaultPda, collateralVaultPda, settlementFeeVaultPda;
   
   let collateralMintKeypair;
   const collateralDecimal = 9;
@@ -65,6 +91,7 @@ describe("token-deployer with transfer hook", () => {
         collateralVault: collateralVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        depositor: null,
       })
       .signers([ecosystemPartnerKeypair])
       .rpc({ commitment: "confirmed" });
@@ -79,6 +106,340 @@ describe("token-deployer with transfer hook", () => {
     }
   }
 
+  // Swaps only route through mock jupiter, and the USDC fixture is only usable, when built with `--features localnet`
+  function builtForLocalnet() {
+    return tokenDeployerProgram.idl.constants?.some((c) =>
+      c.value.includes(mockJupiterProgram.programId.toBase58())
+    );
+  }
+
+  // Fails tests that need a localnet build instead of silently skipping them, unless skipping
+  // is asked for with `SKIP_LOCALNET_TESTS=1`
+  function requireLocalnetBuild(test, reason) {
+    if (builtForLocalnet()) {
+      return;
+    }
+    if (!process.env.SKIP_LOCALNET_TESTS) {
+      throw new Error(`Built without the localnet feature, run \`anchor test -- --features localnet\` ${reason}`);
+    }
+    test.skip();
+  }
+
+  // Mints fixture USDC or collateral to any token account through mock jupiter, which holds both mint
+  // authorities so routes can pay out either side
+  async function mintWithMockJupiter(destinationMint, destination, amount) {
+    const [jupiterMintAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("jupiter-mint-auth")],
+      mockJupiterProgram.programId
+    );
+
+    await mockJupiterProgram.methods
+      .route(
+        [{ swap: { saber: {} }, percent: 100, inputIndex: 0, outputIndex: 1 }],
+        new anchor.BN(0),
+        new anchor.BN(amount),
+        0,
+        0
+      )
+      .accounts({
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        userTransferAuthority: wallet.publicKey,
+        userSourceTokenAccount: walletCollateralAccount,
+        userDestinationTokenAccount: destination,
+        destinationTokenAccount: null,
+        destinationMint,
+        platformFeeAccount: null,
+        eventAuthority: mockJupiterProgram.programId,
+        program: mockJupiterProgram.programId,
+        sourceMint: collateralMintKeypair.publicKey,
+        mintAuthority: jupiterMintAuthority,
+        destinationTokenProgram: tokenProgramFor(destinationMint),
+      })
+      .rpc({ commitment: "confirmed" });
+  }
+
+  // Checks the transaction fails with the given `ErrorCode` variant
+  async function expectTxToFailWith(txPromise, errorCode) {
+    try {
+      await txPromise;
+      return false;
+    } catch (error) {
+      return error.error?.errorCode?.code === errorCode
+        || (error.logs ?? []).some((log) => log.includes(`Error Code: ${errorCode}`));
+    }
+  }
+
+  async function ensureWalletIsApprover() {
+    const config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    if (config.approvers.some((approver) => approver.equals(wallet.publicKey))) {
+      return;
+    }
+
+    await tokenDeployerProgram.methods
+      .addApprover(wallet.publicKey)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+  }
+
+  // Registers a merchant paid out to its own wallet and creates the vault token accounts swaps
+  // route through. Purchases are paid with the partner's uSPs, so only usable when built for localnet
+  async function setupMerchant() {
+    const merchantKeypair = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(merchantKeypair.publicKey, LAMPORTS_PER_SOL),
+      "confirmed"
+    );
+    const merchantWallet = merchantKeypair.publicKey;
+
+    const [merchantPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant"), mintKeypair.publicKey.toBuffer(), merchantWallet.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const [merchantBalancePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant_balance"), merchantWallet.toBuffer(), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const [jupiterMintAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("jupiter-mint-auth")],
+      mockJupiterProgram.programId
+    );
+    const vaultInputTokenAccount = getAssociatedTokenAddressSync(
+      collateralMintKeypair.publicKey,
+      vaultPda,
+      true,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const vaultOutputTokenAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      vaultPda,
+      true,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const merchantUsdcAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      merchantWallet,
+      false,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+
+    await tokenDeployerProgram.methods
+      .registerMerchant("Coffee Shop", "food", merchantWallet)
+      .accounts({
+        config: configPda,
+        payer: ecosystemPartnerKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        merchantWallet,
+        registeredMerchant: merchantPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const createAtasTx = new Transaction();
+    for (const [account, owner, mint] of [
+      [vaultInputTokenAccount, vaultPda, collateralMintKeypair.publicKey],
+      [vaultOutputTokenAccount, vaultPda, USDC_MINT],
+      [merchantUsdcAccount, merchantWallet, USDC_MINT],
+    ]) {
+      if (!(await connection.getAccountInfo(account, "confirmed"))) {
+        createAtasTx.add(
+          createAssociatedTokenAccountInstruction(
+            wallet.publicKey,
+            account,
+            owner,
+            mint,
+            tokenProgramFor(mint),
+            ASSOCIATED_TOKEN_PROGRAM_ID
+          )
+        );
+      }
+    }
+    await sendAndConfirmTransaction(connection, createAtasTx, [wallet.payer], {
+      commitment: "confirmed",
+    });
+
+    const purchaseReceiptPda = (purchaseReference) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("purchase_receipt"),
+          merchantWallet.toBuffer(),
+          createHash("sha256").update(purchaseReference).digest(),
+        ],
+        tokenDeployerProgram.programId
+      )[0];
+
+    // Pays the merchant `usdcOut` for `swapAmount` of the partner's uSPs
+    const purchase = (purchaseReference, swapAmount, usdcOut) =>
+      tokenDeployerProgram.methods
+        .swap(
+          new anchor.BN(swapAmount),
+          purchaseReference,
+          new anchor.BN(usdcOut),
+          mockJupiterProgram.coder.instruction.encode("route", {
+            routePlan: [{ swap: { saber: {} }, percent: 100, inputIndex: 0, outputIndex: 1 }],
+            inAmount: new anchor.BN(swapAmount),
+            quotedOutAmount: new anchor.BN(usdcOut),
+            slippageBps: 50,
+            platformFeeBps: 0,
+          })
+        )
+        .accounts({
+          payer: ecosystemPartnerKeypair.publicKey,
+          inputMint: collateralMintKeypair.publicKey,
+          inputMintProgram: TOKEN_2022_PROGRAM_ID,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          vault: vaultPda,
+          vaultInputTokenAccount,
+          vaultOutputTokenAccount,
+          jupiterProgram: mockJupiterProgram.programId,
+          mint: mintKeypair.publicKey,
+          config: configPda,
+          ecosystemConfig: ecosystemConfigPda,
+          userTokenAccount: ecosystemPartnerTokenAccount,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          feeVaultAuthority: feeVaultAuthorityPda,
+          collateralVault: collateralVaultPda,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          merchantBalance: merchantBalancePda,
+          merchantWallet,
+          registeredMerchant: merchantPda,
+          purchaseReceipt: purchaseReceiptPda(purchaseReference),
+          // Only used when the merchant settles instantly
+          merchantPayoutTokenAccount: merchantUsdcAccount,
+          settlementFeeVault: settlementFeeVaultPda,
+          priceFeed: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: TOKEN_2022_PROGRAM_ID, isSigner: false, isWritable: false },
+          { pubkey: vaultPda, isSigner: false, isWritable: true },
+          { pubkey: vaultInputTokenAccount, isSigner: false, isWritable: true },
+          { pubkey: vaultOutputTokenAccount, isSigner: false, isWritable: true },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: USDC_MINT, isSigner: false, isWritable: true },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: collateralMintKeypair.publicKey, isSigner: false, isWritable: true },
+          { pubkey: jupiterMintAuthority, isSigner: false, isWritable: false },
+          { pubkey: tokenProgramFor(USDC_MINT), isSigner: false, isWritable: false },
+        ])
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+        .signers([ecosystemPartnerKeypair])
+        .rpc({ commitment: "confirmed" });
+
+    // Creates the merchant's next withdrawal request and returns its address
+    const requestWithdrawal = async (amount) => {
+      const { requestCount } = await tokenDeployerProgram.account.merchantBalance.fetch(merchantBalancePda, "confirmed");
+      const [withdrawalRequestPda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("withdrawal_request"),
+          merchantWallet.toBuffer(),
+          ecosystemConfigPda.toBuffer(),
+          requestCount.toArrayLike(Buffer, "le", 8),
+        ],
+        tokenDeployerProgram.programId
+      );
+
+      await tokenDeployerProgram.methods
+        .createWithdrawalRequest(new anchor.BN(amount))
+        .accounts({
+          payer: merchantWallet,
+          merchantBalance: merchantBalancePda,
+          config: configPda,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+          registeredMerchant: merchantPda,
+          withdrawalRequest: withdrawalRequestPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([merchantKeypair])
+        .rpc({ commitment: "confirmed" });
+
+      return withdrawalRequestPda;
+    };
+
+    const approveWithdrawal = (withdrawalRequestPda, approver = wallet.payer) =>
+      tokenDeployerProgram.methods
+        .approveWithdrawalRequest()
+        .accounts({
+          config: configPda,
+          approver: approver.publicKey,
+          withdrawalRequest: withdrawalRequestPda,
+          merchant: merchantWallet,
+          merchantBalance: merchantBalancePda,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          vault: vaultPda,
+          vaultOutputTokenAccount,
+          registeredMerchant: merchantPda,
+          merchantTokenAccount: merchantUsdcAccount,
+          feeVaultAuthority: feeVaultAuthorityPda,
+          settlementFeeVault: settlementFeeVaultPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([approver])
+        .rpc({ commitment: "confirmed" });
+
+    const rejectWithdrawal = (withdrawalRequestPda, reasonCode, approver = wallet.payer) =>
+      tokenDeployerProgram.methods
+        .rejectWithdrawalRequest(reasonCode)
+        .accounts({
+          config: configPda,
+          approver: approver.publicKey,
+          merchant: merchantWallet,
+          withdrawalRequest: withdrawalRequestPda,
+          merchantBalance: merchantBalancePda,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+        })
+        .signers([approver])
+        .rpc({ commitment: "confirmed" });
+
+    const cancelWithdrawal = (withdrawalRequestPda, payer = merchantKeypair) =>
+      tokenDeployerProgram.methods
+        .cancelWithdrawalRequest()
+        .accounts({
+          payer: payer.publicKey,
+          withdrawalRequest: withdrawalRequestPda,
+          merchantBalance: merchantBalancePda,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    return {
+      merchantKeypair,
+      merchantPda,
+      merchantBalancePda,
+      vaultPda,
+      vaultInputTokenAccount,
+      vaultOutputTokenAccount,
+      merchantUsdcAccount,
+      purchaseReceiptPda,
+      purchase,
+      requestWithdrawal,
+      approveWithdrawal,
+      rejectWithdrawal,
+      cancelWithdrawal,
+    };
+  }
+
   before(async () => {
     await connection.confirmTransaction(
       await connection.requestAirdrop(ecosystemPartnerKeypair.publicKey, 2 * LAMPORTS_PER_SOL)
@@ -186,7 +547,8 @@ describe("token-deployer with transfer hook", () => {
         collateralMintKeypair.publicKey,
         partnerCollateralAccount,
         wallet.publicKey,
-        1000 * 10 ** collateralDecimal,
+        // Every test deposits from the partner, so keep enough collateral for the whole suite
+        10_000 * 10 ** collateralDecimal,
         [],
         TOKEN_2022_PROGRAM_ID
       ),
@@ -203,6 +565,24 @@ describe("token-deployer with transfer hook", () => {
     await sendAndConfirmTransaction(connection, mintCollateralTx, [wallet.payer], {
       commitment: "confirmed",
     });
+
+    const [jupiterMintAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("jupiter-mint-auth")],
+      mockJupiterProgram.programId
+    );
+    const collateralMintAuthorityTx = new Transaction().add(
+      createSetAuthorityInstruction(
+        collateralMintKeypair.publicKey,
+        wallet.publicKey,
+        AuthorityType.MintTokens,
+        jupiterMintAuthority,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(connection, collateralMintAuthorityTx, [wallet.payer], {
+      commitment: "confirmed",
+    });
   });
 
   beforeEach(async () => {
@@ -217,6 +597,7 @@ describe("token-deployer with transfer hook", () => {
     const maxMintingCap = new anchor.BN(1000 * 10 ** decimals);
     const withdrawalFee = 2000; // 20% fee (2000 basis points)
     const depositFee = 2000; // 20% fee (2000 basis points)
+    const redemptionFee = 1000; // 10% fee (1000 basis points)
 
     [mintAuthorityPda] = PublicKey.findProgramAddressSync(
       [Buffer.from("mint_authority"), mintKeypair.publicKey.toBuffer()],
@@ -242,6 +623,11 @@ describe("token-deployer with transfer hook", () => {
       [Buffer.from("collateral_vault"), mintKeypair.publicKey.toBuffer()],
       tokenDeployerProgram.programId
     );
+    
+    [settlementFeeVaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("settlement_fee_vault"), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
 
     await tokenDeployerProgram.methods
       .createEcosystem({
@@ -255,6 +641,8 @@ describe("token-deployer with transfer hook", () => {
         withdrawalFeeBasisPoints: withdrawalFee,
         depositFeeBasisPoints: depositFee,
         collateralTokenMint: collateralMintKeypair.publicKey, 
+        redemptionFeeBasisPoints: redemptionFee,
+        settlementMint: USDC_MINT,
       })
       .accounts({
         config: configPda,
@@ -266,8 +654,11 @@ describe("token-deployer with transfer hook", () => {
         collateralTokenMint: collateralMintKeypair.publicKey,
         feeVault: feeVaultPda,
         collateralVault: collateralVaultPda,
+        settlementMint: USDC_MINT,
+        settlementFeeVault: settlementFeeVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        settlementTokenProgram: tokenProgramFor(USDC_MINT),
         systemProgram: SystemProgram.programId,
       })
       .signers([mintKeypair])
@@ -401,6 +792,7 @@ describe("token-deployer with transfer hook", () => {
         collateralVault: collateralVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        depositor: null,
       })
       .signers([unauthorizedWalletKeypair]);
     
@@ -464,6 +856,7 @@ describe("token-deployer with transfer hook", () => {
         collateralVault: collateralVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        depositor: null,
       })
       .signers([ecosystemPartnerKeypair]);
       
@@ -516,6 +909,7 @@ describe("token-deployer with transfer hook", () => {
         collateralTokenMint: collateralMintKeypair.publicKey,
         feeVaultAuthority: feeVaultAuthorityPda,
         feeVault: feeVaultPda,
+        collateralVault: collateralVaultPda,
         destinationAccount: walletCollateralAccount,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
@@ -555,6 +949,7 @@ describe("token-deployer with transfer hook", () => {
         collateralTokenMint: collateralMintKeypair.publicKey,
         feeVaultAuthority: feeVaultAuthorityPda,
         feeVault: feeVaultPda,
+        collateralVault: collateralVaultPda,
         destinationAccount: walletCollateralAccount,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
@@ -564,6 +959,290 @@ describe("token-deployer with transfer hook", () => {
     assert(emptyCollectionFailed, "Should not be able to collect fees when vault is empty");
   });
 
+  it("Allow ecosystem partner to redeem collateral", async () => {
+    const mintAmount = 100 * 10 ** decimals;
+    await mintTokensWithPartner(mintAmount);
+
+    const redeemAmount = 40 * 10 ** decimals;
+    const redemptionFee = 1000; // 10% fee (1000 basis points)
+    const feeAmount = (redeemAmount * redemptionFee) / 10000;
+
+    const partnerCollateralBefore = await connection.getTokenAccountBalance(partnerCollateralAccount, "confirmed");
+    const collateralVaultBefore = await connection.getTokenAccountBalance(collateralVaultPda, "confirmed");
+    const feeVaultBefore = await connection.getTokenAccountBalance(feeVaultPda, "confirmed");
+    const mintInfoBefore = await getMint(connection, mintKeypair.publicKey, "confirmed", TOKEN_2022_PROGRAM_ID);
+
+    console.log("Non partner trying to redeem (it should fail)");
+    const unauthorizedRedeemTx = tokenDeployerProgram.methods
+      .redeemEcosystem(new anchor.BN(redeemAmount))
+      .accounts({
+        payer: unauthorizedWalletKeypair.publicKey,
+        config: configPda,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        userTokenAccount: unauthorizedTokenAccount,
+        collateralTokenMint: collateralMintKeypair.publicKey,
+        userCollateralAccount: unauthorizedCollateralAccount,
+        feeVaultAuthority: feeVaultAuthorityPda,
+        feeVault: feeVaultPda,
+        collateralVault: collateralVaultPda,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([unauthorizedWalletKeypair]);
+
+    const unauthorizedRedeemFailed = await expectTxToFail(unauthorizedRedeemTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedRedeemFailed, "Only the ecosystem partner should be able to redeem");
+
+    await tokenDeployerProgram.methods
+      .redeemEcosystem(new anchor.BN(redeemAmount))
+      .accounts({
+        payer: ecosystemPartnerKeypair.publicKey,
+        config: configPda,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        userTokenAccount: ecosystemPartnerTokenAccount,
+        collateralTokenMint: collateralMintKeypair.publicKey,
+        userCollateralAccount: partnerCollateralAccount,
+        feeVaultAuthority: feeVaultAuthorityPda,
+        feeVault: feeVaultPda,
+        collateralVault: collateralVaultPda,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const partnerCollateralAfter = await connection.getTokenAccountBalance(partnerCollateralAccount, "confirmed");
+    const collateralVaultAfter = await connection.getTokenAccountBalance(collateralVaultPda, "confirmed");
+    const feeVaultAfter = await connection.getTokenAccountBalance(feeVaultPda, "confirmed");
+    const mintInfoAfter = await getMint(connection, mintKeypair.publicKey, "confirmed", TOKEN_2022_PROGRAM_ID);
+
+    assert.equal(
+      Number(mintInfoBefore.supply) - Number(mintInfoAfter.supply),
+      redeemAmount,
+      "Redeemed uSP should be burned"
+    );
+    assert.equal(
+      Number(partnerCollateralAfter.value.amount) - Number(partnerCollateralBefore.value.amount),
+      redeemAmount - feeAmount,
+      "Partner should receive collateral minus the redemption fee"
+    );
+    assert.equal(
+      Number(feeVaultAfter.value.amount) - Number(feeVaultBefore.value.amount),
+      feeAmount,
+      "Redemption fee should be moved to the fee vault"
+    );
+    assert.equal(
+      Number(collateralVaultBefore.value.amount) - Number(collateralVaultAfter.value.amount),
+      redeemAmount,
+      "Collateral vault should release the full redeemed amount"
+    );
+    assert.equal(
+      Number(collateralVaultAfter.value.amount),
+      Number(mintInfoAfter.supply),
+      "Collateral in vault should equal uSP supply (1:1 collateralization)"
+    );
+  });
+
+  it("Proof of reserves", async () => {
+    const getReserveStatus = () =>
+      tokenDeployerProgram.methods
+        .getReserveStatus()
+        .accounts({
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          feeVault: feeVaultPda,
+          collateralVault: collateralVaultPda,
+        })
+        .view({ commitment: "confirmed" });
+
+    let reserveStatus = await getReserveStatus();
+    assert.isNull(reserveStatus.collateralizationRatioBps, "Ratio should be empty while nothing is minted");
+
+    console.log("Reading reserves without the vaults of an open ecosystem (it should fail)");
+    const missingVaultsFailed = await expectTxToFailWith(
+      tokenDeployerProgram.methods
+        .getReserveStatus()
+        .accounts({
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          feeVault: null,
+          collateralVault: null,
+        })
+        .view({ commitment: "confirmed" }),
+      "MissingVaultAccount"
+    );
+    assert(missingVaultsFailed, "Vaults should only be optional once the ecosystem is closed");
+
+    // 20% deposit fee, so 80 uSP are minted and 20 collateral tokens go to the fee vault
+    await mintTokensWithPartner(100 * 10 ** decimals);
+
+    reserveStatus = await getReserveStatus();
+    assert.equal(reserveStatus.mintSupply.toNumber(), 80 * 10 ** decimals);
+    assert.equal(reserveStatus.collateralVaultBalance.toNumber(), 80 * 10 ** decimals);
+    assert.equal(reserveStatus.feeVaultBalance.toNumber(), 20 * 10 ** decimals);
+    assert.equal(reserveStatus.merchantBalancesOwed.toNumber(), 0);
+    assert.equal(reserveStatus.collateralizationRatioBps.toNumber(), 10000, "uSP should be fully backed");
+  });
+
+  it("Collateral invariant catches under-backed deposits", async () => {
+    // A 1% transfer fee leaves the collateral vault with less than the uSP minted for a deposit
+    const feeCollateralMintKeypair = Keypair.generate();
+    const feeCollateralMintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const partnerFeeCollateralAccount = getAssociatedTokenAddressSync(
+      feeCollateralMintKeypair.publicKey,
+      ecosystemPartnerKeypair.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const createFeeCollateralMintTx = new Transaction().add(
+      SystemProgram.createAccount({
+        fromPubkey: wallet.publicKey,
+        newAccountPubkey: feeCollateralMintKeypair.publicKey,
+        space: feeCollateralMintLen,
+        lamports: await connection.getMinimumBalanceForRentExemption(feeCollateralMintLen),
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        feeCollateralMintKeypair.publicKey,
+        wallet.publicKey,
+        wallet.publicKey,
+        100,
+        BigInt(1000 * 10 ** collateralDecimal),
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(
+        feeCollateralMintKeypair.publicKey,
+        collateralDecimal,
+        wallet.publicKey,
+        null,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createAssociatedTokenAccountInstruction(
+        wallet.publicKey,
+        partnerFeeCollateralAccount,
+        ecosystemPartnerKeypair.publicKey,
+        feeCollateralMintKeypair.publicKey,
+        TOKEN_2022_PROGRAM_ID,
+        ASSOCIATED_TOKEN_PROGRAM_ID
+      ),
+      createMintToInstruction(
+        feeCollateralMintKeypair.publicKey,
+        partnerFeeCollateralAccount,
+        wallet.publicKey,
+        100 * 10 ** collateralDecimal,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(connection, createFeeCollateralMintTx, [wallet.payer, feeCollateralMintKeypair], {
+      commitment: "confirmed",
+    });
+
+    const feeEcosystemMintKeypair = Keypair.generate();
+    const feeEcosystemPda = (seed) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), feeEcosystemMintKeypair.publicKey.toBuffer()],
+        tokenDeployerProgram.programId
+      )[0];
+    const feeEcosystemPartnerTokenAccount = getAssociatedTokenAddressSync(
+      feeEcosystemMintKeypair.publicKey,
+      ecosystemPartnerKeypair.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+
+    await tokenDeployerProgram.methods
+      .createEcosystem({
+        decimals,
+        name: "Fee Collateral",
+        symbol: "FEE",
+        uri: "https://example.com/metadata.json",
+        transferHookProgramId: transferHookProgram.programId,
+        ecosystemPartnerWallet: ecosystemPartnerKeypair.publicKey,
+        maxMintingCap: new anchor.BN(1000 * 10 ** decimals),
+        withdrawalFeeBasisPoints: 0,
+        depositFeeBasisPoints: 0,
+        collateralTokenMint: feeCollateralMintKeypair.publicKey,
+        redemptionFeeBasisPoints: 0,
+        settlementMint: USDC_MINT,
+      })
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mintAccount: feeEcosystemMintKeypair.publicKey,
+        mintAuthority: feeEcosystemPda("mint_authority"),
+        ecosystemConfig: feeEcosystemPda("ecosystem_config"),
+        feeVaultAuthority: feeEcosystemPda("fee_vault_authority"),
+        collateralTokenMint: feeCollateralMintKeypair.publicKey,
+        feeVault: feeEcosystemPda("fee_vault"),
+        collateralVault: feeEcosystemPda("collateral_vault"),
+        settlementMint: USDC_MINT,
+        settlementFeeVault: feeEcosystemPda("settlement_fee_vault"),
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        settlementTokenProgram: tokenProgramFor(USDC_MINT),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([feeEcosystemMintKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    await sendAndConfirmTransaction(
+      connection,
+      new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          wallet.publicKey,
+          feeEcosystemPartnerTokenAccount,
+          ecosystemPartnerKeypair.publicKey,
+          feeEcosystemMintKeypair.publicKey,
+          TOKEN_2022_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        )
+      ),
+      [wallet.payer],
+      { commitment: "confirmed" }
+    );
+
+    console.log("Depositing collateral that arrives short (it should fail)");
+    let underBackedDepositLogs = [];
+    try {
+      await tokenDeployerProgram.methods
+        .depositEcosystem(new anchor.BN(10 * 10 ** decimals))
+        .accounts({
+          payer: ecosystemPartnerKeypair.publicKey,
+          config: configPda,
+          mint: feeEcosystemMintKeypair.publicKey,
+          mintAuthority: feeEcosystemPda("mint_authority"),
+          toAta: feeEcosystemPartnerTokenAccount,
+          ecosystemConfig: feeEcosystemPda("ecosystem_config"),
+          collateralTokenMint: feeCollateralMintKeypair.publicKey,
+          userCollateralAccount: partnerFeeCollateralAccount,
+          feeVault: feeEcosystemPda("fee_vault"),
+          collateralVault: feeEcosystemPda("collateral_vault"),
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          depositor: null,
+        })
+        .signers([ecosystemPartnerKeypair])
+        .rpc({ commitment: "confirmed" });
+    } catch (error) {
+      underBackedDepositLogs = error.logs ?? [];
+    }
+    assert(
+      underBackedDepositLogs.some((log) => log.includes("Error Code: InvariantViolation")),
+      "Deposits that leave uSP under-backed should fail with InvariantViolation"
+    );
+    // The settlement invariant has no test, its accounting moves the vault and the merchant balances
+    // together, so only tokens leaving the vault outside the program could trip it
+    assert(
+      underBackedDepositLogs.some((log) => log.includes("Invariant violated: CollateralCoversSupply")),
+      "The log should name the collateral invariant"
+    );
+  });
+
   it("Collecting fees", async () => {
     await mintTokensWithPartner(150 * 10 ** decimals);
     
@@ -602,6 +1281,7 @@ describe("token-deployer with transfer hook", () => {
         collateralTokenMint: collateralMintKeypair.publicKey,
         feeVaultAuthority: feeVaultAuthorityPda,
         feeVault: feeVaultPda,
+        collateralVault: collateralVaultPda,
         destinationAccount: unauthorizedCollateralAccount,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
@@ -612,30 +1292,284 @@ describe("token-deployer with transfer hook", () => {
     assert(unauthorizedCollectionFailed, "Unauthorized users should not be able to collect fees");
   });
 
-  it("Tests transfer hook whitelist", async () => {
-    await mintTokensWithPartner(100 * 10 ** decimals);
-    
-    [whitelistStatusPda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("whitelist"), destinationTokenAccount.toBuffer()],
-      transferHookProgram.programId
-    );
-    
-    const addToWhitelistTx = await transferHookProgram.methods
-      .addToWhitelist()
-      .accounts({
-        signer: wallet.publicKey,
-        user: destinationTokenAccount,
-        config: transferHookConfigPda,
-        whiteListStatus: whitelistStatusPda,
-        systemProgram: SystemProgram.programId,
-      })
-      .transaction();
-    await sendAndConfirmTransaction(connection, addToWhitelistTx, [wallet.payer], {
-      commitment: "confirmed",
-    });
-    console.log("Destination account added to whitelist");
+  it("Collecting settlement fees", async function () {
+    const settlementFeeVault = await connection.getAccountInfo(settlementFeeVaultPda, "confirmed");
+    assert(settlementFeeVault, "Settlement fee vault should be created with the ecosystem");
 
-    const transferInstruction = await createTransferCheckedWithTransferHookInstruction(
+    const ownerUsdcAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      wallet.publicKey,
+      false,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    if (!(await connection.getAccountInfo(ownerUsdcAccount, "confirmed"))) {
+      const createOwnerAtaTx = new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          wallet.publicKey,
+          ownerUsdcAccount,
+          wallet.publicKey,
+          USDC_MINT,
+          tokenProgramFor(USDC_MINT),
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        )
+      );
+      await sendAndConfirmTransaction(connection, createOwnerAtaTx, [wallet.payer], {
+        commitment: "confirmed",
+      });
+    }
+
+    const collectSettlementFeesTx = (payer = wallet.payer) =>
+      tokenDeployerProgram.methods
+        .collectSettlementFees()
+        .accounts({
+          config: configPda,
+          payer: payer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          feeVaultAuthority: feeVaultAuthorityPda,
+          settlementFeeVault: settlementFeeVaultPda,
+          destinationAccount: ownerUsdcAccount,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Collecting settlement fees before any withdrawal (it should fail)");
+    const noFeesFailed = await expectTxToFailWith(collectSettlementFeesTx(), "NoFeesToCollect");
+    assert(noFeesFailed, "Collecting with no settlement fees should fail with NoFeesToCollect");
+
+    requireLocalnetBuild(this, "to accrue settlement fees through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    await ensureWalletIsApprover();
+
+    const merchant = await setupMerchant();
+    const usdcOut = 5 * 10 ** 6;
+    await merchant.purchase("fees-1", 10 * 10 ** decimals, usdcOut);
+    await merchant.approveWithdrawal(await merchant.requestWithdrawal(usdcOut));
+
+    // 20% withdrawal fee
+    const expectedFee = usdcOut / 5;
+    let ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.collectedSettlementFees.toNumber(), expectedFee, "Withdrawal fee should be tracked");
+
+    console.log("Collecting settlement fees with unauthorized wallet (it should fail)");
+    const unauthorizedCollectFailed = await expectTxToFailWith(
+      collectSettlementFeesTx(unauthorizedWalletKeypair),
+      "Unauthorized"
+    );
+    assert(unauthorizedCollectFailed, "Only fee collectors should be able to collect settlement fees");
+
+    const ownerBalanceBefore = await connection.getTokenAccountBalance(ownerUsdcAccount, "confirmed");
+    await collectSettlementFeesTx();
+    const ownerBalanceAfter = await connection.getTokenAccountBalance(ownerUsdcAccount, "confirmed");
+
+    assert.equal(
+      Number(ownerBalanceAfter.value.amount) - Number(ownerBalanceBefore.value.amount),
+      expectedFee,
+      "Collected settlement fees should be sent to the owner"
+    );
+
+    ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.collectedSettlementFees.toNumber(), 0, "Collected settlement fees should be reset");
+  });
+
+  it("Allow owner to update ecosystem fees", async () => {
+    const newDepositFee = 500; // 5% fee (500 basis points)
+
+    console.log("Setting fee above 100% (it should fail)");
+    const invalidFeeTx = tokenDeployerProgram.methods
+      .updateEcosystemFees(10001, 2000, 1000, null)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      });
+    const invalidFeeFailed = await expectTxToFail(invalidFeeTx.rpc({ commitment: "confirmed" }));
+    assert(invalidFeeFailed, "Fees above 10000 basis points should be rejected");
+
+    console.log("Non owner updating fees (it should fail)");
+    const unauthorizedFeeTx = tokenDeployerProgram.methods
+      .updateEcosystemFees(0, 0, 0, null)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedFeeFailed = await expectTxToFail(unauthorizedFeeTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedFeeFailed, "Non owner should not be able to update fees");
+
+    await tokenDeployerProgram.methods
+      .updateEcosystemFees(newDepositFee, 2000, 1000, null)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const mintAmount = 100 * 10 ** decimals;
+    await mintTokensWithPartner(mintAmount);
+
+    const partnerTokenInfo = await connection.getTokenAccountBalance(ecosystemPartnerTokenAccount, "confirmed");
+    assert.equal(
+      Number(partnerTokenInfo.value.amount),
+      mintAmount - (mintAmount * newDepositFee) / 10000,
+      "Deposit should be charged the updated fee"
+    );
+
+    const scheduleFeesTx = (effectiveAt) =>
+      tokenDeployerProgram.methods
+        .updateEcosystemFees(3000, 2000, 1000, effectiveAt === null ? null : new anchor.BN(effectiveAt))
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Increasing fees immediately (it should fail)");
+    const immediateIncreaseFailed = await expectTxToFailWith(scheduleFeesTx(null), "FeeIncreaseNoticeTooShort");
+    assert(immediateIncreaseFailed, "Fee increases should not apply immediately");
+
+    console.log("Increasing fees with less than the notice period (it should fail)");
+    const shortNoticeFailed = await expectTxToFailWith(
+      scheduleFeesTx(Math.floor(Date.now() / 1000) + 24 * 60 * 60),
+      "FeeIncreaseNoticeTooShort"
+    );
+    assert(shortNoticeFailed, "Fee increases should be scheduled at least the notice period ahead");
+
+    // 7 day notice period, plus a day of margin for the validator clock
+    const effectiveAt = Math.floor(Date.now() / 1000) + 8 * 24 * 60 * 60;
+    await tokenDeployerProgram.methods
+      .updateEcosystemFees(3000, 2000, 1000, new anchor.BN(effectiveAt))
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.depositFeeBasisPoints, newDepositFee, "Scheduled fee should not apply before its effective time");
+    assert.equal(ecosystemConfig.pendingFeeUpdate.depositFeeBasisPoints, 3000, "Scheduled fee should be stored as pending");
+    assert.equal(ecosystemConfig.pendingFeeUpdate.effectiveAt.toNumber(), effectiveAt, "Scheduled fee should keep its effective time");
+  });
+
+  it("Allow owner to update ecosystem metadata", async () => {
+    const metadataBefore = await getTokenMetadata(connection, mintKeypair.publicKey, "confirmed", TOKEN_2022_PROGRAM_ID);
+    assert.equal(metadataBefore.name, "Bonk", "Metadata name should be set on creation");
+    assert.equal(metadataBefore.symbol, "BONK", "Metadata symbol should be set on creation");
+    assert(metadataBefore.updateAuthority.equals(mintAuthorityPda), "Mint authority PDA should own metadata updates");
+
+    const updateArgs = {
+      name: "Bonk Rewards",
+      symbol: null,
+      uri: "https://example.com/bonk-rewards.json",
+      additionalMetadata: [{ key: "collateral", value: collateralMintKeypair.publicKey.toBase58() }],
+      removedKeys: [],
+    };
+
+    console.log("Non owner updating metadata (it should fail)");
+    const unauthorizedUpdateTx = tokenDeployerProgram.methods
+      .updateEcosystemMetadata(updateArgs)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        mintAuthority: mintAuthorityPda,
+        ecosystemConfig: ecosystemConfigPda,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([unauthorizedWalletKeypair]);
+
+    const unauthorizedUpdateFailed = await expectTxToFail(unauthorizedUpdateTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedUpdateFailed, "Non owner should not be able to update metadata");
+
+    await tokenDeployerProgram.methods
+      .updateEcosystemMetadata(updateArgs)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        mintAuthority: mintAuthorityPda,
+        ecosystemConfig: ecosystemConfigPda,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const metadataAfter = await getTokenMetadata(connection, mintKeypair.publicKey, "confirmed", TOKEN_2022_PROGRAM_ID);
+    assert.equal(metadataAfter.name, "Bonk Rewards", "Name should be updated");
+    assert.equal(metadataAfter.symbol, "BONK", "Symbol should be unchanged");
+    assert.equal(metadataAfter.uri, "https://example.com/bonk-rewards.json", "Uri should be updated");
+    assert.deepEqual(
+      metadataAfter.additionalMetadata,
+      [["collateral", collateralMintKeypair.publicKey.toBase58()]],
+      "Additional metadata field should be added"
+    );
+
+    // The longer name is written before the uri shrinks, so the mint briefly needs more rent than its final size
+    const longName = "Bonk Rewards ".repeat(8).trim();
+    await tokenDeployerProgram.methods
+      .updateEcosystemMetadata({
+        name: longName,
+        symbol: null,
+        uri: "https://example.com/b.json",
+        additionalMetadata: [],
+        removedKeys: ["collateral"],
+      })
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        mintAuthority: mintAuthorityPda,
+        ecosystemConfig: ecosystemConfigPda,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const metadataShrunk = await getTokenMetadata(connection, mintKeypair.publicKey, "confirmed", TOKEN_2022_PROGRAM_ID);
+    assert.equal(metadataShrunk.name, longName, "Name should grow");
+    assert.equal(metadataShrunk.uri, "https://example.com/b.json", "Uri should shrink");
+    assert.deepEqual(metadataShrunk.additionalMetadata, [], "Removed key should be gone");
+  });
+
+  it("Tests transfer hook whitelist", async () => {
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    
+    [whitelistStatusPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("whitelist"), destinationTokenAccount.toBuffer()],
+      transferHookProgram.programId
+    );
+    
+    const addToWhitelistTx = await transferHookProgram.methods
+      .addToWhitelist()
+      .accounts({
+        signer: wallet.publicKey,
+        user: destinationTokenAccount,
+        config: transferHookConfigPda,
+        whiteListStatus: whitelistStatusPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .transaction();
+    await sendAndConfirmTransaction(connection, addToWhitelistTx, [wallet.payer], {
+      commitment: "confirmed",
+    });
+    console.log("Destination account added to whitelist");
+
+    const transferInstruction = await createTransferCheckedWithTransferHookInstruction(
       connection,
       ecosystemPartnerTokenAccount,
       mintKeypair.publicKey,
@@ -710,7 +1644,7 @@ describe("token-deployer with transfer hook", () => {
     );
   });
   
-  it("global and ecosystem freeze functionality", async () => {
+  it("global and ecosystem pause flags", async () => {
     const mintAmount = 50 * 10 ** decimals;
     
     await mintTokensWithPartner(mintAmount);
@@ -725,7 +1659,7 @@ describe("token-deployer with transfer hook", () => {
     console.log("Initial token supply:", initialSupply / (10 ** decimals));
     
     await tokenDeployerProgram.methods
-      .toggleGlobalFreeze()
+      .setGlobalPauseFlags(PAUSE_DEPOSIT)
       .accounts({
         config: configPda,
         payer: wallet.publicKey,
@@ -748,6 +1682,7 @@ describe("token-deployer with transfer hook", () => {
         collateralVault: collateralVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        depositor: null,
       })
       .signers([ecosystemPartnerKeypair]);
     
@@ -759,7 +1694,7 @@ describe("token-deployer with transfer hook", () => {
     
     console.log("Disabling global freeze and enabling ecosystem freeze: ");
     await tokenDeployerProgram.methods
-      .toggleGlobalFreeze()
+      .setGlobalPauseFlags(0)
       .accounts({
         config: configPda,
         payer: wallet.publicKey,
@@ -767,7 +1702,7 @@ describe("token-deployer with transfer hook", () => {
       .rpc({ commitment: "confirmed" });
     
     await tokenDeployerProgram.methods
-      .toggleEcosystemFreeze()
+      .setEcosystemPauseFlags(PAUSE_DEPOSIT)
       .accounts({
         config: configPda,
         payer: wallet.publicKey,
@@ -792,6 +1727,7 @@ describe("token-deployer with transfer hook", () => {
         collateralVault: collateralVaultPda,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        depositor: null,
       })
       .signers([ecosystemPartnerKeypair]);
     
@@ -800,7 +1736,7 @@ describe("token-deployer with transfer hook", () => {
     
     console.log("Disabling ecosystem freeze");
     await tokenDeployerProgram.methods
-      .toggleEcosystemFreeze()
+      .setEcosystemPauseFlags(0)
       .accounts({
         config: configPda,
         payer: wallet.publicKey,
@@ -822,9 +1758,41 @@ describe("token-deployer with transfer hook", () => {
       "Supply should increase by the amount minus fees after unfreezing"
     );
     
-    console.log("Non owner trying to toggle global and ecosystem freeze");
+    console.log("Pausing only swaps and withdrawals, deposits keep working");
+    await tokenDeployerProgram.methods
+      .setGlobalPauseFlags(PAUSE_SWAP | PAUSE_WITHDRAWAL)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await mintTokensWithPartner(mintAmount);
+
+    const config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.equal(config.pauseFlags, PAUSE_SWAP | PAUSE_WITHDRAWAL, "Global pause flags should be stored");
+
+    console.log("Setting unknown pause flags (it should fail)");
+    const unknownFlagsTx = tokenDeployerProgram.methods
+      .setGlobalPauseFlags(1 << 7)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      });
+    const unknownFlagsFailed = await expectTxToFail(unknownFlagsTx.rpc({ commitment: "confirmed" }));
+    assert(unknownFlagsFailed, "Unknown pause flags should be rejected");
+
+    await tokenDeployerProgram.methods
+      .setGlobalPauseFlags(0)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    console.log("Non owner trying to set global and ecosystem pause flags");
     const unauthorizedToggleGlobalTx = tokenDeployerProgram.methods
-      .toggleGlobalFreeze()
+      .setGlobalPauseFlags(PAUSE_DEPOSIT)
       .accounts({
         config: configPda,
         payer: unauthorizedWalletKeypair.publicKey,
@@ -832,7 +1800,7 @@ describe("token-deployer with transfer hook", () => {
       .signers([unauthorizedWalletKeypair]);
     
     const unauthorizedToggleEcosystemTx = tokenDeployerProgram.methods
-      .toggleEcosystemFreeze()
+      .setEcosystemPauseFlags(PAUSE_DEPOSIT)
       .accounts({
         config: configPda,
         payer: unauthorizedWalletKeypair.publicKey,
@@ -844,7 +1812,2104 @@ describe("token-deployer with transfer hook", () => {
     const unauthorizedGlobalFreezeFailed = await expectTxToFail(unauthorizedToggleGlobalTx.rpc({ commitment: "confirmed" }));
     const unauthorizedEcosystemFreezeFailed = await expectTxToFail(unauthorizedToggleEcosystemTx.rpc({ commitment: "confirmed" }));
     
-    assert(unauthorizedGlobalFreezeFailed, "Non owner global freeze toggle");
-    assert(unauthorizedEcosystemFreezeFailed, "Non owner ecosystem freeze toggle");
+    assert(unauthorizedGlobalFreezeFailed, "Non owner global pause");
+    assert(unauthorizedEcosystemFreezeFailed, "Non owner ecosystem pause");
+  });
+
+  it("Mint rate limit", async () => {
+    const mintWindowLimit = 50 * 10 ** decimals;
+    const mintWindowDuration = 24 * 60 * 60;
+
+    console.log("Updating mint rate limit with unauthorized wallet (it should fail)");
+    const unauthorizedUpdateTx = tokenDeployerProgram.methods
+      .updateMintRateLimit(new anchor.BN(mintWindowLimit), new anchor.BN(mintWindowDuration))
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedUpdateFailed = await expectTxToFail(unauthorizedUpdateTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedUpdateFailed, "Only owner or cap manager should be able to update the mint rate limit");
+
+    await tokenDeployerProgram.methods
+      .updateMintRateLimit(new anchor.BN(mintWindowLimit), new anchor.BN(mintWindowDuration))
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    // 20% deposit fee, so 40 uSP are minted
+    await mintTokensWithPartner(50 * 10 ** decimals);
+
+    console.log("Depositing over the mint rate limit (it should fail)");
+    const overLimitFailed = await expectTxToFail(mintTokensWithPartner(20 * 10 ** decimals));
+    assert(overLimitFailed, "Deposit minting over the window limit should fail");
+
+    await mintTokensWithPartner(10 * 10 ** decimals);
+
+    // A few seconds of the 24h window drained between the deposits
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.closeTo(
+      ecosystemConfig.mintedInWindow.toNumber(),
+      48 * 10 ** decimals,
+      0.1 * 10 ** decimals,
+      "Minted uSP should be tracked for the window"
+    );
+  });
+
+  it("Mint rate limit window boundary", async () => {
+    const sleep = (seconds) => new Promise((resolve) => setTimeout(resolve, seconds * 1000));
+
+    // 10 uSP per 20 seconds, deposits pay a 20% fee so 12.5 collateral mints the whole limit
+    await tokenDeployerProgram.methods
+      .updateMintRateLimit(new anchor.BN(10 * 10 ** decimals), new anchor.BN(20))
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    // A fixed window would start here and reset 20 seconds later
+    await mintTokensWithPartner(1 * 10 ** decimals);
+    await sleep(16);
+    await mintTokensWithPartner(12.5 * 10 ** decimals);
+
+    // Past where a fixed window would have reset, but only about 6 seconds worth has drained
+    await sleep(6);
+    console.log("Minting the whole limit again right after the window boundary (it should fail)");
+    const boundaryMintFailed = await expectTxToFailWith(
+      mintTokensWithPartner(12.5 * 10 ** decimals),
+      "MintRateLimitExceeded"
+    );
+    assert(boundaryMintFailed, "The limit should not refill all at once at a window boundary");
+
+    await mintTokensWithPartner(2.5 * 10 ** decimals);
+  });
+
+  it("Delegated depositors and partner wallet rotation", async () => {
+    const hotWalletKeypair = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(hotWalletKeypair.publicKey, LAMPORTS_PER_SOL)
+    );
+
+    const hotWalletCollateralAccount = getAssociatedTokenAddressSync(
+      collateralMintKeypair.publicKey,
+      hotWalletKeypair.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const hotWalletTokenAccount = getAssociatedTokenAddressSync(
+      mintKeypair.publicKey,
+      hotWalletKeypair.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const setupHotWalletTx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        wallet.publicKey,
+        hotWalletCollateralAccount,
+        hotWalletKeypair.publicKey,
+        collateralMintKeypair.publicKey,
+        TOKEN_2022_PROGRAM_ID,
+        ASSOCIATED_TOKEN_PROGRAM_ID
+      ),
+      createAssociatedTokenAccountInstruction(
+        wallet.publicKey,
+        hotWalletTokenAccount,
+        hotWalletKeypair.publicKey,
+        mintKeypair.publicKey,
+        TOKEN_2022_PROGRAM_ID,
+        ASSOCIATED_TOKEN_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(connection, setupHotWalletTx, [wallet.payer], {
+      commitment: "confirmed",
+    });
+    await mintWithMockJupiter(collateralMintKeypair.publicKey, hotWalletCollateralAccount, 100 * 10 ** collateralDecimal);
+
+    const [depositorPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("depositor"), mintKeypair.publicKey.toBuffer(), hotWalletKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+
+    const hotWalletDeposit = (amount, depositor) =>
+      tokenDeployerProgram.methods
+        .depositEcosystem(new anchor.BN(amount))
+        .accounts({
+          payer: hotWalletKeypair.publicKey,
+          config: configPda,
+          mint: mintKeypair.publicKey,
+          mintAuthority: mintAuthorityPda,
+          toAta: hotWalletTokenAccount,
+          ecosystemConfig: ecosystemConfigPda,
+          collateralTokenMint: collateralMintKeypair.publicKey,
+          userCollateralAccount: hotWalletCollateralAccount,
+          feeVault: feeVaultPda,
+          collateralVault: collateralVaultPda,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          depositor,
+        })
+        .signers([hotWalletKeypair])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Depositing from a wallet that isn't a depositor (it should fail)");
+    const notDepositorFailed = await expectTxToFail(hotWalletDeposit(10 * 10 ** decimals, null));
+    assert(notDepositorFailed, "Only the partner or delegated depositors should be able to deposit");
+
+    const addHotWalletDepositor = (signer, depositAllowance) =>
+      tokenDeployerProgram.methods
+        .addDepositor(new anchor.BN(depositAllowance))
+        .accounts({
+          config: configPda,
+          payer: signer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          depositorWallet: hotWalletKeypair.publicKey,
+          depositor: depositorPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    await addHotWalletDepositor(ecosystemPartnerKeypair, 20 * 10 ** decimals);
+
+    await hotWalletDeposit(15 * 10 ** decimals, depositorPda);
+
+    console.log("Adding a depositor that is already active (it should fail)");
+    const activeDepositorFailed = await expectTxToFailWith(
+      addHotWalletDepositor(ecosystemPartnerKeypair, 50 * 10 ** decimals),
+      "DepositorAlreadyActive"
+    );
+    assert(activeDepositorFailed, "Re-adding a current depositor should not reset its deposit total");
+
+    const depositor = await tokenDeployerProgram.account.depositor.fetch(depositorPda, "confirmed");
+    assert.equal(depositor.totalDeposited.toNumber(), 15 * 10 ** decimals, "Depositor deposits should be tracked");
+
+    console.log("Depositing over the depositor allowance (it should fail)");
+    const overAllowanceFailed = await expectTxToFail(hotWalletDeposit(10 * 10 ** decimals, depositorPda));
+    assert(overAllowanceFailed, "Deposits over the depositor allowance should fail");
+
+    const newPartnerKeypair = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(newPartnerKeypair.publicKey, LAMPORTS_PER_SOL)
+    );
+
+    const partnerWalletProposal = (method, signer) =>
+      method
+        .accounts({
+          config: configPda,
+          payer: signer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    await partnerWalletProposal(
+      tokenDeployerProgram.methods.updatePartnerWallet(unauthorizedWalletKeypair.publicKey),
+      ecosystemPartnerKeypair
+    );
+
+    console.log("Cancelling the partner wallet proposal with unauthorized wallet (it should fail)");
+    const unauthorizedCancelFailed = await expectTxToFailWith(
+      partnerWalletProposal(tokenDeployerProgram.methods.cancelPartnerWalletProposal(), unauthorizedWalletKeypair),
+      "Unauthorized"
+    );
+    assert(unauthorizedCancelFailed, "Only owner or partner should be able to cancel a partner wallet proposal");
+
+    await partnerWalletProposal(tokenDeployerProgram.methods.cancelPartnerWalletProposal(), wallet.payer);
+
+    let ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.pendingPartnerWallet, null, "Cancelled partner wallet proposal should be cleared");
+
+    console.log("Cancelling without a pending partner wallet (it should fail)");
+    const noProposalCancelFailed = await expectTxToFailWith(
+      partnerWalletProposal(tokenDeployerProgram.methods.cancelPartnerWalletProposal(), ecosystemPartnerKeypair),
+      "NoPendingPartnerWallet"
+    );
+    assert(noProposalCancelFailed, "Cancelling should fail without a pending partner wallet");
+
+    console.log("Rotating partner wallet with unauthorized wallet (it should fail)");
+    const unauthorizedRotateTx = tokenDeployerProgram.methods
+      .updatePartnerWallet(newPartnerKeypair.publicKey)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedRotateFailed = await expectTxToFail(unauthorizedRotateTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedRotateFailed, "Only owner or partner should be able to rotate the partner wallet");
+
+    await tokenDeployerProgram.methods
+      .updatePartnerWallet(newPartnerKeypair.publicKey)
+      .accounts({
+        config: configPda,
+        payer: ecosystemPartnerKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert(ecosystemConfig.ecosystemPartnerWallet.equals(ecosystemPartnerKeypair.publicKey), "Partner wallet should only change once accepted");
+    assert(ecosystemConfig.pendingPartnerWallet.equals(newPartnerKeypair.publicKey), "New partner wallet should be pending");
+
+    const acceptPartnerWallet = (signer) =>
+      tokenDeployerProgram.methods
+        .acceptPartnerWallet()
+        .accounts({
+          config: configPda,
+          payer: signer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+        })
+        .signers([signer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Accepting the partner wallet from another wallet (it should fail)");
+    const wrongAcceptFailed = await expectTxToFail(acceptPartnerWallet(unauthorizedWalletKeypair));
+    assert(wrongAcceptFailed, "Only the proposed partner wallet should be able to accept");
+
+    await acceptPartnerWallet(newPartnerKeypair);
+
+    ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert(ecosystemConfig.ecosystemPartnerWallet.equals(newPartnerKeypair.publicKey), "Partner wallet should be rotated");
+    assert.equal(ecosystemConfig.pendingPartnerWallet, null, "Pending partner wallet should be cleared");
+    assert.equal(ecosystemConfig.partnerEpoch.toNumber(), 1, "Partner epoch should be bumped");
+
+    const oldPartnerDepositFailed = await expectTxToFail(mintTokensWithPartner(1 * 10 ** decimals));
+    assert(oldPartnerDepositFailed, "Old partner wallet should not be able to deposit after rotation");
+
+    console.log("Depositing with a depositor added by the old partner wallet (it should fail)");
+    const revokedDepositorFailed = await expectTxToFailWith(
+      hotWalletDeposit(1 * 10 ** decimals, depositorPda),
+      "DepositorRevoked"
+    );
+    assert(revokedDepositorFailed, "Depositors added by the old partner wallet should stop working after rotation");
+
+    await addHotWalletDepositor(newPartnerKeypair, 5 * 10 ** decimals);
+
+    const refreshedDepositor = await tokenDeployerProgram.account.depositor.fetch(depositorPda, "confirmed");
+    assert.equal(refreshedDepositor.totalDeposited.toNumber(), 0, "Re-added depositor should start a new deposit total");
+    assert.equal(refreshedDepositor.depositAllowance.toNumber(), 5 * 10 ** decimals, "Re-added depositor should get the new allowance");
+    assert.equal(refreshedDepositor.partnerEpoch.toNumber(), 1, "Re-added depositor should be on the current partner epoch");
+    assert(refreshedDepositor.rentPayer.equals(newPartnerKeypair.publicKey), "Re-adding should make the new partner the rent payer");
+
+    await hotWalletDeposit(3 * 10 ** decimals, depositorPda);
+
+    const rentPayerBalanceBefore = await connection.getBalance(newPartnerKeypair.publicKey, "confirmed");
+    const depositorRent = await connection.getBalance(depositorPda, "confirmed");
+
+    console.log("Removing the depositor with the wrong rent payer (it should fail)");
+    const wrongRentPayerFailed = await expectTxToFail(
+      tokenDeployerProgram.methods
+        .removeDepositor()
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          depositor: depositorPda,
+          rentPayer: ecosystemPartnerKeypair.publicKey,
+        })
+        .rpc({ commitment: "confirmed" })
+    );
+    assert(wrongRentPayerFailed, "Depositor rent should only go back to whoever paid it");
+
+    await tokenDeployerProgram.methods
+      .removeDepositor()
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        depositor: depositorPda,
+        rentPayer: newPartnerKeypair.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const rentPayerBalanceAfter = await connection.getBalance(newPartnerKeypair.publicKey, "confirmed");
+    assert.equal(
+      rentPayerBalanceAfter - rentPayerBalanceBefore,
+      depositorRent,
+      "Depositor rent should be refunded to the wallet that added it"
+    );
+
+    const removedDepositorFailed = await expectTxToFail(hotWalletDeposit(1 * 10 ** decimals, null));
+    assert(removedDepositorFailed, "Removed depositor should not be able to deposit");
+  });
+
+  it("Ecosystem sunset and close", async () => {
+    const [depositorPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("depositor"), mintKeypair.publicKey.toBuffer(), unauthorizedWalletKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+
+    await tokenDeployerProgram.methods
+      .addDepositor(null)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        depositorWallet: unauthorizedWalletKeypair.publicKey,
+        depositor: depositorPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const holderDeposit = (amount) =>
+      tokenDeployerProgram.methods
+        .depositEcosystem(new anchor.BN(amount))
+        .accounts({
+          payer: unauthorizedWalletKeypair.publicKey,
+          config: configPda,
+          mint: mintKeypair.publicKey,
+          mintAuthority: mintAuthorityPda,
+          toAta: unauthorizedTokenAccount,
+          ecosystemConfig: ecosystemConfigPda,
+          collateralTokenMint: collateralMintKeypair.publicKey,
+          userCollateralAccount: unauthorizedCollateralAccount,
+          feeVault: feeVaultPda,
+          collateralVault: collateralVaultPda,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          depositor: depositorPda,
+        })
+        .signers([unauthorizedWalletKeypair])
+        .rpc({ commitment: "confirmed" });
+
+    // 20% deposit fee, so 40 uSP are minted to the holder
+    await holderDeposit(50 * 10 ** decimals);
+
+    const ownerUsdcAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      wallet.publicKey,
+      false,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    if (!(await connection.getAccountInfo(ownerUsdcAccount, "confirmed"))) {
+      const createOwnerAtaTx = new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          wallet.publicKey,
+          ownerUsdcAccount,
+          wallet.publicKey,
+          USDC_MINT,
+          tokenProgramFor(USDC_MINT),
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        )
+      );
+      await sendAndConfirmTransaction(connection, createOwnerAtaTx, [wallet.payer], {
+        commitment: "confirmed",
+      });
+    }
+
+    const closeEcosystem = () =>
+      tokenDeployerProgram.methods
+        .closeEcosystem()
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          collateralTokenMint: collateralMintKeypair.publicKey,
+          feeVaultAuthority: feeVaultAuthorityPda,
+          feeVault: feeVaultPda,
+          collateralVault: collateralVaultPda,
+          partnerCollateralAccount,
+          treasuryCollateralAccount: walletCollateralAccount,
+          settlementMint: USDC_MINT,
+          settlementFeeVault: settlementFeeVaultPda,
+          treasurySettlementAccount: ownerUsdcAccount,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          settlementTokenProgram: tokenProgramFor(USDC_MINT),
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Closing an active ecosystem (it should fail)");
+    const closeActiveFailed = await expectTxToFail(closeEcosystem());
+    assert(closeActiveFailed, "Only a sunsetting ecosystem should be closable");
+
+    const redemptionWindow = 3;
+
+    console.log("Beginning sunset with unauthorized wallet (it should fail)");
+    const unauthorizedSunsetTx = tokenDeployerProgram.methods
+      .beginSunset(new anchor.BN(redemptionWindow))
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedSunsetFailed = await expectTxToFail(unauthorizedSunsetTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedSunsetFailed, "Only the owner should be able to begin a sunset");
+
+    await tokenDeployerProgram.methods
+      .beginSunset(new anchor.BN(redemptionWindow))
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    let ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.deepEqual(ecosystemConfig.status, { sunsetting: {} }, "Ecosystem should be sunsetting");
+
+    console.log("Depositing during sunset (it should fail)");
+    const sunsetDepositFailed = await expectTxToFail(holderDeposit(10 * 10 ** decimals));
+    assert(sunsetDepositFailed, "Deposits should stop during sunset");
+
+    console.log("Closing before the redemption window ends (it should fail)");
+    const earlyCloseFailed = await expectTxToFail(closeEcosystem());
+    assert(earlyCloseFailed, "Ecosystem should not close while the redemption window is open");
+
+    const holderCollateralBefore = await connection.getTokenAccountBalance(unauthorizedCollateralAccount, "confirmed");
+
+    // Non partner holders can redeem during the redemption window
+    const holderRedeem = (amount) =>
+      tokenDeployerProgram.methods
+        .redeemEcosystem(new anchor.BN(amount))
+        .accounts({
+          payer: unauthorizedWalletKeypair.publicKey,
+          config: configPda,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          userTokenAccount: unauthorizedTokenAccount,
+          collateralTokenMint: collateralMintKeypair.publicKey,
+          userCollateralAccount: unauthorizedCollateralAccount,
+          feeVaultAuthority: feeVaultAuthorityPda,
+          feeVault: feeVaultPda,
+          collateralVault: collateralVaultPda,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([unauthorizedWalletKeypair])
+        .rpc({ commitment: "confirmed" });
+
+    const holderRedeemAmount = 20 * 10 ** decimals;
+    await holderRedeem(holderRedeemAmount);
+
+    const holderCollateralAfter = await connection.getTokenAccountBalance(unauthorizedCollateralAccount, "confirmed");
+    assert.equal(
+      Number(holderCollateralAfter.value.amount) - Number(holderCollateralBefore.value.amount),
+      holderRedeemAmount * 0.9,
+      "Holder should receive collateral minus the redemption fee"
+    );
+
+    await new Promise((resolve) => setTimeout(resolve, (redemptionWindow + 2) * 1000));
+
+    console.log("Redeeming as a holder after the redemption window (it should fail)");
+    const lateRedeemFailed = await expectTxToFailWith(holderRedeem(1 * 10 ** decimals), "RedemptionWindowClosed");
+    assert(lateRedeemFailed, "Holders should only be able to redeem until the redemption window ends");
+
+    // Stands in for withdrawal fees nobody collected before the close
+    await mintWithMockJupiter(USDC_MINT, settlementFeeVaultPda, 2 * 10 ** 6);
+
+    const collateralVaultBefore = await connection.getTokenAccountBalance(collateralVaultPda, "confirmed");
+    const feeVaultBefore = await connection.getTokenAccountBalance(feeVaultPda, "confirmed");
+    const settlementFeeVaultBefore = await connection.getTokenAccountBalance(settlementFeeVaultPda, "confirmed");
+    const partnerCollateralBefore = await connection.getTokenAccountBalance(partnerCollateralAccount, "confirmed");
+    const treasuryCollateralBefore = await connection.getTokenAccountBalance(walletCollateralAccount, "confirmed");
+    const treasurySettlementBefore = await connection.getTokenAccountBalance(ownerUsdcAccount, "confirmed");
+
+    await closeEcosystem();
+
+    const partnerCollateralAfter = await connection.getTokenAccountBalance(partnerCollateralAccount, "confirmed");
+    const treasuryCollateralAfter = await connection.getTokenAccountBalance(walletCollateralAccount, "confirmed");
+    assert.equal(
+      Number(partnerCollateralAfter.value.amount) - Number(partnerCollateralBefore.value.amount),
+      Number(collateralVaultBefore.value.amount),
+      "Leftover collateral should be returned to the partner"
+    );
+    assert.equal(
+      Number(treasuryCollateralAfter.value.amount) - Number(treasuryCollateralBefore.value.amount),
+      Number(feeVaultBefore.value.amount),
+      "Leftover fees should be returned to the treasury"
+    );
+    const treasurySettlementAfter = await connection.getTokenAccountBalance(ownerUsdcAccount, "confirmed");
+    assert.equal(
+      Number(treasurySettlementAfter.value.amount) - Number(treasurySettlementBefore.value.amount),
+      Number(settlementFeeVaultBefore.value.amount),
+      "Leftover settlement fees should be returned to the treasury"
+    );
+
+    assert.isNull(await connection.getAccountInfo(collateralVaultPda, "confirmed"), "Collateral vault should be closed");
+    assert.isNull(await connection.getAccountInfo(feeVaultPda, "confirmed"), "Fee vault should be closed");
+    assert.isNull(await connection.getAccountInfo(settlementFeeVaultPda, "confirmed"), "Settlement fee vault should be closed");
+
+    ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.deepEqual(ecosystemConfig.status, { closed: {} }, "Ecosystem should be closed");
+    assert.equal(ecosystemConfig.collectedSettlementFees.toNumber(), 0, "Collected settlement fees should be reset");
+
+    // The vaults are gone, so a closed ecosystem reports them empty
+    const closedReserveStatus = await tokenDeployerProgram.methods
+      .getReserveStatus()
+      .accounts({
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        feeVault: null,
+        collateralVault: null,
+      })
+      .view({ commitment: "confirmed" });
+    assert.equal(closedReserveStatus.collateralVaultBalance.toNumber(), 0);
+    assert.equal(closedReserveStatus.feeVaultBalance.toNumber(), 0);
+  });
+
+  it("Merchant registry", async () => {
+    const merchantKeypair = Keypair.generate();
+    const merchantWallet = merchantKeypair.publicKey;
+    const [merchantPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant"), mintKeypair.publicKey.toBuffer(), merchantWallet.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+
+    console.log("Registering merchant with unauthorized wallet (it should fail)");
+    const unauthorizedRegisterTx = tokenDeployerProgram.methods
+      .registerMerchant("Coffee Shop", "food", merchantWallet)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        merchantWallet,
+        registeredMerchant: merchantPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedRegisterFailed = await expectTxToFail(unauthorizedRegisterTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedRegisterFailed, "Only owner or ecosystem partner should be able to register merchants");
+
+    await tokenDeployerProgram.methods
+      .registerMerchant("Coffee Shop", "food", merchantWallet)
+      .accounts({
+        config: configPda,
+        payer: ecosystemPartnerKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        merchantWallet,
+        registeredMerchant: merchantPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    let merchant = await tokenDeployerProgram.account.merchant.fetch(merchantPda, "confirmed");
+    assert.equal(merchant.name, "Coffee Shop");
+    assert(merchant.payoutAddress.equals(merchantWallet), "Payout address should be stored");
+    assert.isTrue(merchant.active, "Merchant should be active after registration");
+
+    await tokenDeployerProgram.methods
+      .updateMerchant("Coffee Bar", null)
+      .accounts({
+        config: configPda,
+        payer: ecosystemPartnerKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        registeredMerchant: merchantPda,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const updatePayoutAddressTx = (payoutAddress, payer) =>
+      tokenDeployerProgram.methods
+        .updateMerchantPayoutAddress(payoutAddress)
+        .accounts({
+          config: configPda,
+          payer: payer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          registeredMerchant: merchantPda,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Changing the merchant payout address as the ecosystem partner (it should fail)");
+    const partnerPayoutChangeFailed = await expectTxToFailWith(
+      updatePayoutAddressTx(ecosystemPartnerKeypair.publicKey, ecosystemPartnerKeypair),
+      "Unauthorized"
+    );
+    assert(partnerPayoutChangeFailed, "The ecosystem partner should not be able to redirect merchant payouts");
+
+    const merchantPayoutAddress = Keypair.generate().publicKey;
+    await updatePayoutAddressTx(merchantPayoutAddress, merchantKeypair);
+    merchant = await tokenDeployerProgram.account.merchant.fetch(merchantPda, "confirmed");
+    assert.equal(merchant.name, "Coffee Bar", "Name should be updated");
+    assert(merchant.payoutAddress.equals(merchantPayoutAddress), "Merchant should be able to change its payout address");
+
+    // The owner can recover a merchant that lost access to its payout wallet
+    const payoutAddress = Keypair.generate().publicKey;
+    await updatePayoutAddressTx(payoutAddress, wallet.payer);
+
+    await tokenDeployerProgram.methods
+      .setMerchantStatus(false, true)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        registeredMerchant: merchantPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    merchant = await tokenDeployerProgram.account.merchant.fetch(merchantPda, "confirmed");
+    assert(merchant.payoutAddress.equals(payoutAddress), "Payout address should be updated");
+    assert.isFalse(merchant.active, "Merchant should be suspended");
+    assert.isTrue(merchant.balanceFrozen, "Merchant balance should be frozen");
+
+    console.log("Claiming a legacy merchant without a merchant balance (it should fail)");
+    const legacyMerchantKeypair = Keypair.generate();
+    const [legacyMerchantPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant"), mintKeypair.publicKey.toBuffer(), legacyMerchantKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const [legacyMerchantBalancePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant_balance"), legacyMerchantKeypair.publicKey.toBuffer(), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const airdropSignature = await connection.requestAirdrop(legacyMerchantKeypair.publicKey, LAMPORTS_PER_SOL);
+    await connection.confirmTransaction(airdropSignature, "confirmed");
+    const claimWithoutBalanceFailed = await expectTxToFail(
+      tokenDeployerProgram.methods
+        .claimLegacyMerchant("Tea House", "food")
+        .accounts({
+          payer: legacyMerchantKeypair.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          merchantBalance: legacyMerchantBalancePda,
+          registeredMerchant: legacyMerchantPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([legacyMerchantKeypair])
+        .rpc({ commitment: "confirmed" })
+    );
+    assert(claimWithoutBalanceFailed, "Only merchants holding a balance from before the registry can register themselves");
+  });
+
+  it("Purchase flow through mock jupiter", async function () {
+    requireLocalnetBuild(this, "to route swaps through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+
+    const merchantWallet = Keypair.generate().publicKey;
+    const [merchantPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant"), mintKeypair.publicKey.toBuffer(), merchantWallet.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+
+    await tokenDeployerProgram.methods
+      .registerMerchant("Coffee Shop", "food", merchantWallet)
+      .accounts({
+        config: configPda,
+        payer: ecosystemPartnerKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        merchantWallet,
+        registeredMerchant: merchantPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const vaultInputTokenAccount = getAssociatedTokenAddressSync(
+      collateralMintKeypair.publicKey,
+      vaultPda,
+      true,
+      TOKEN_2022_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    const vaultOutputTokenAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      vaultPda,
+      true,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+
+    const createVaultAtasTx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        wallet.publicKey,
+        vaultInputTokenAccount,
+        vaultPda,
+        collateralMintKeypair.publicKey,
+        TOKEN_2022_PROGRAM_ID,
+        ASSOCIATED_TOKEN_PROGRAM_ID
+      ),
+      createAssociatedTokenAccountInstruction(
+        wallet.publicKey,
+        vaultOutputTokenAccount,
+        vaultPda,
+        USDC_MINT,
+        tokenProgramFor(USDC_MINT),
+        ASSOCIATED_TOKEN_PROGRAM_ID
+      )
+    );
+    await sendAndConfirmTransaction(connection, createVaultAtasTx, [wallet.payer], {
+      commitment: "confirmed",
+    });
+
+    const [jupiterMintAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("jupiter-mint-auth")],
+      mockJupiterProgram.programId
+    );
+
+    const swapAmount = 10 * 10 ** decimals;
+    const usdcOut = 5 * 10 ** 6;
+    const routeData = mockJupiterProgram.coder.instruction.encode("route", {
+      routePlan: [{ swap: { saber: {} }, percent: 100, inputIndex: 0, outputIndex: 1 }],
+      inAmount: new anchor.BN(swapAmount),
+      quotedOutAmount: new anchor.BN(usdcOut),
+      slippageBps: 50,
+      platformFeeBps: 0,
+    });
+
+    // Mock jupiter route accounts, unused optional accounts are passed as the mock program id
+    const routeAccounts = [
+      { pubkey: TOKEN_2022_PROGRAM_ID, isSigner: false, isWritable: false },
+      { pubkey: vaultPda, isSigner: false, isWritable: true },
+      { pubkey: vaultInputTokenAccount, isSigner: false, isWritable: true },
+      { pubkey: vaultOutputTokenAccount, isSigner: false, isWritable: true },
+      { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: USDC_MINT, isSigner: false, isWritable: true },
+      { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: collateralMintKeypair.publicKey, isSigner: false, isWritable: true },
+      { pubkey: jupiterMintAuthority, isSigner: false, isWritable: false },
+      { pubkey: tokenProgramFor(USDC_MINT), isSigner: false, isWritable: false },
+    ];
+
+    const purchaseReference = "order-1";
+    const [merchantBalancePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("merchant_balance"), merchantWallet.toBuffer(), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const [purchaseReceiptPda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("purchase_receipt"),
+        merchantWallet.toBuffer(),
+        createHash("sha256").update(purchaseReference).digest(),
+      ],
+      tokenDeployerProgram.programId
+    );
+
+    const [priceFeedPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("price_feed"), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+
+    const swapTx = (priceFeed = priceFeedPda, minSettlementOut = usdcOut) =>
+      tokenDeployerProgram.methods
+        .swap(new anchor.BN(swapAmount), purchaseReference, new anchor.BN(minSettlementOut), routeData)
+        .accounts({
+          payer: ecosystemPartnerKeypair.publicKey,
+          inputMint: collateralMintKeypair.publicKey,
+          inputMintProgram: TOKEN_2022_PROGRAM_ID,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          vault: vaultPda,
+          vaultInputTokenAccount,
+          vaultOutputTokenAccount,
+          jupiterProgram: mockJupiterProgram.programId,
+          mint: mintKeypair.publicKey,
+          config: configPda,
+          ecosystemConfig: ecosystemConfigPda,
+          userTokenAccount: ecosystemPartnerTokenAccount,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          feeVaultAuthority: feeVaultAuthorityPda,
+          collateralVault: collateralVaultPda,
+          collateralTokenProgram: TOKEN_2022_PROGRAM_ID,
+          merchantBalance: merchantBalancePda,
+          merchantWallet,
+          registeredMerchant: merchantPda,
+          purchaseReceipt: purchaseReceiptPda,
+          merchantPayoutTokenAccount: null,
+          settlementFeeVault: null,
+          priceFeed,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(routeAccounts)
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+        .signers([ecosystemPartnerKeypair]);
+
+    const updatePriceFeed = (price, payer = wallet.payer, exponent = -2) =>
+      tokenDeployerProgram.methods
+        .updatePriceFeed(new anchor.BN(price), exponent)
+        .accounts({
+          config: configPda,
+          payer: payer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          priceFeed: priceFeedPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Updating price feed with unauthorized wallet (it should fail)");
+    const unauthorizedPriceFailed = await expectTxToFail(updatePriceFeed(50, unauthorizedWalletKeypair));
+    assert(unauthorizedPriceFailed, "Only owner or price updater should be able to update the price feed");
+
+    console.log("Updating price feed with an out of range exponent (it should fail)");
+    const exponentFailed = await expectTxToFail(updatePriceFeed(50, wallet.payer, 19));
+    assert(exponentFailed, "Price exponents outside -18..=18 should be rejected");
+
+    // 1 USDC per collateral token, while mock jupiter pays 0.5 USDC per collateral token
+    await updatePriceFeed(100);
+
+    const setPriceOracle = (priceFeed, feedId, maxStaleness) =>
+      tokenDeployerProgram.methods
+        .setPriceOracle(priceFeed, feedId, 100, maxStaleness)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    await setPriceOracle(priceFeedPda, Array(32).fill(0), new anchor.BN(60));
+
+    console.log("Swapping without the configured price feed (it should fail)");
+    const missingFeedFailed = await expectTxToFailWith(swapTx(null).rpc({ commitment: "confirmed" }), "InvalidPriceFeed");
+    assert(missingFeedFailed, "Swap should require the configured price feed");
+
+    console.log("Swapping at half the oracle price (it should fail)");
+    const deviationFailed = await expectTxToFailWith(swapTx().rpc({ commitment: "confirmed" }), "PriceDeviationExceeded");
+    assert(deviationFailed, "Swap output too far below the oracle price should fail");
+
+    await updatePriceFeed(50);
+
+    console.log("Swapping while swaps are paused for the ecosystem (it should fail)");
+    const setSwapPause = (pauseFlags) =>
+      tokenDeployerProgram.methods
+        .setEcosystemPauseFlags(pauseFlags)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    await setSwapPause(PAUSE_SWAP);
+    const pausedSwapFailed = await expectTxToFail(swapTx().rpc({ commitment: "confirmed" }));
+    assert(pausedSwapFailed, "Swap should fail while swaps are paused");
+    await setSwapPause(0);
+
+    console.log("Swapping with a minimum USDC output above the route output (it should fail)");
+    // The oracle stays configured at the route price, so only the min-out guard can reject this
+    const slippageFailed = await expectTxToFailWith(
+      swapTx(priceFeedPda, usdcOut + 1).rpc({ commitment: "confirmed" }),
+      "SlippageExceeded"
+    );
+    assert(slippageFailed, "Swap receiving less than min_settlement_out should fail with SlippageExceeded");
+
+    // The Pyth fixtures are published at unix time 0, so their age is the validator clock. Allow
+    // prices far older than that unless a test is about staleness
+    const fixtureMaxStaleness = new anchor.BN("9000000000000000000");
+
+    console.log("Swapping against a Pyth update for another feed (it should fail)");
+    await setPriceOracle(PYTH_PRICE_UPDATE, Array(32).fill(1), fixtureMaxStaleness);
+    const feedIdFailed = await expectTxToFailWith(
+      swapTx(PYTH_PRICE_UPDATE).rpc({ commitment: "confirmed" }),
+      "PriceFeedIdMismatch"
+    );
+    assert(feedIdFailed, "Pyth updates should only be accepted for the configured feed id");
+
+    console.log("Swapping against a Pyth price with a wide confidence interval (it should fail)");
+    await setPriceOracle(PYTH_PRICE_UPDATE_WIDE_CONF, PYTH_FEED_ID, fixtureMaxStaleness);
+    const wideConfFailed = await expectTxToFailWith(
+      swapTx(PYTH_PRICE_UPDATE_WIDE_CONF).rpc({ commitment: "confirmed" }),
+      "PriceConfidenceTooWide"
+    );
+    assert(wideConfFailed, "Pyth prices less certain than the max deviation should be rejected");
+
+    console.log("Swapping against a Pyth price older than the max staleness (it should fail)");
+    await setPriceOracle(PYTH_PRICE_UPDATE, PYTH_FEED_ID, new anchor.BN(1));
+    const staleFeedFailed = await expectTxToFailWith(
+      swapTx(PYTH_PRICE_UPDATE).rpc({ commitment: "confirmed" }),
+      "StalePriceFeed"
+    );
+    assert(staleFeedFailed, "Pyth prices older than the max staleness should be rejected");
+
+    // Same 0.5 USDC price as the program-owned feed, read from the Pyth layout
+    await setPriceOracle(PYTH_PRICE_UPDATE, PYTH_FEED_ID, fixtureMaxStaleness);
+
+    const partnerBalanceBefore = await connection.getTokenAccountBalance(ecosystemPartnerTokenAccount, "confirmed");
+    await swapTx(PYTH_PRICE_UPDATE).rpc({ commitment: "confirmed" });
+    const partnerBalanceAfter = await connection.getTokenAccountBalance(ecosystemPartnerTokenAccount, "confirmed");
+
+    assert.equal(
+      Number(partnerBalanceBefore.value.amount) - Number(partnerBalanceAfter.value.amount),
+      swapAmount,
+      "Swapped uSPs should be burned"
+    );
+
+    const merchantBalance = await tokenDeployerProgram.account.merchantBalance.fetch(merchantBalancePda, "confirmed");
+    assert.equal(merchantBalance.balance.toNumber(), usdcOut, "Merchant should be credited with the swap output");
+
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.totalMerchantBalance.toNumber(), usdcOut, "USDC owed to merchants should be tracked");
+
+    const receipt = await tokenDeployerProgram.account.purchaseReceipt.fetch(purchaseReceiptPda, "confirmed");
+    assert.equal(receipt.uspBurned.toNumber(), swapAmount);
+    assert.equal(receipt.settlementCredited.toNumber(), usdcOut);
+
+    console.log("Paying the same purchase reference twice (it should fail)");
+    const duplicateSwapFailed = await expectTxToFail(swapTx(PYTH_PRICE_UPDATE).rpc({ commitment: "confirmed" }));
+    assert(duplicateSwapFailed, "Purchase reference should only be paid once");
+  });
+
+  it("Refunding purchases", async function () {
+    requireLocalnetBuild(this, "to route swaps through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+
+    const merchant = await setupMerchant();
+    const swapAmount = 10 * 10 ** decimals;
+    const usdcOut = 5 * 10 ** 6;
+
+    const partnerUsdcAccount = getAssociatedTokenAddressSync(
+      USDC_MINT,
+      ecosystemPartnerKeypair.publicKey,
+      false,
+      tokenProgramFor(USDC_MINT),
+      ASSOCIATED_TOKEN_PROGRAM_ID
+    );
+    if (!(await connection.getAccountInfo(partnerUsdcAccount, "confirmed"))) {
+      const createPartnerAtaTx = new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          wallet.publicKey,
+          partnerUsdcAccount,
+          ecosystemPartnerKeypair.publicKey,
+          USDC_MINT,
+          tokenProgramFor(USDC_MINT),
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        )
+      );
+      await sendAndConfirmTransaction(connection, createPartnerAtaTx, [wallet.payer], {
+        commitment: "confirmed",
+      });
+    }
+
+    const [jupiterMintAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("jupiter-mint-auth")],
+      mockJupiterProgram.programId
+    );
+
+    // Buys `collateralOut` back with the purchase's USDC, routed into `routeDestination`
+    const refundTx = (
+      purchaseReference,
+      { collateralOut = swapAmount, minCollateralOut = 1, routeDestination = merchant.vaultInputTokenAccount } = {}
+    ) =>
+      tokenDeployerProgram.methods
+        .refundPurchase(
+          purchaseReference,
+          new anchor.BN(minCollateralOut),
+          mockJupiterProgram.coder.instruction.encode("route", {
+            routePlan: [{ swap: { saber: {} }, percent: 100, inputIndex: 0, outputIndex: 1 }],
+            inAmount: new anchor.BN(usdcOut),
+            quotedOutAmount: new anchor.BN(collateralOut),
+            slippageBps: 50,
+            platformFeeBps: 0,
+          })
+        )
+        .accounts({
+          payer: merchant.merchantKeypair.publicKey,
+          mint: mintKeypair.publicKey,
+          config: configPda,
+          ecosystemConfig: ecosystemConfigPda,
+          merchantBalance: merchant.merchantBalancePda,
+          purchaseReceipt: merchant.purchaseReceiptPda(purchaseReference),
+          registeredMerchant: merchant.merchantPda,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          vault: merchant.vaultPda,
+          vaultOutputTokenAccount: merchant.vaultOutputTokenAccount,
+          userOutputTokenAccount: partnerUsdcAccount,
+          inputMint: collateralMintKeypair.publicKey,
+          inputMintProgram: TOKEN_2022_PROGRAM_ID,
+          vaultInputTokenAccount: merchant.vaultInputTokenAccount,
+          collateralVault: collateralVaultPda,
+          mintAuthority: mintAuthorityPda,
+          userTokenAccount: ecosystemPartnerTokenAccount,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          jupiterProgram: mockJupiterProgram.programId,
+          priceFeed: null,
+        })
+        .remainingAccounts([
+          { pubkey: tokenProgramFor(USDC_MINT), isSigner: false, isWritable: false },
+          { pubkey: merchant.vaultPda, isSigner: false, isWritable: true },
+          { pubkey: merchant.vaultOutputTokenAccount, isSigner: false, isWritable: true },
+          { pubkey: routeDestination, isSigner: false, isWritable: true },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: collateralMintKeypair.publicKey, isSigner: false, isWritable: true },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: mockJupiterProgram.programId, isSigner: false, isWritable: false },
+          { pubkey: USDC_MINT, isSigner: false, isWritable: true },
+          { pubkey: jupiterMintAuthority, isSigner: false, isWritable: false },
+          { pubkey: TOKEN_2022_PROGRAM_ID, isSigner: false, isWritable: false },
+        ])
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+        .signers([merchant.merchantKeypair])
+        .rpc({ commitment: "confirmed" });
+
+    const fetchReceipt = (purchaseReference) =>
+      tokenDeployerProgram.account.purchaseReceipt.fetch(merchant.purchaseReceiptPda(purchaseReference), "confirmed");
+
+    // UsdcDirect, the default policy, pays the credited USDC straight back to the user
+    await merchant.purchase("refund-direct", swapAmount, usdcOut);
+
+    const partnerUsdcBefore = await connection.getTokenAccountBalance(partnerUsdcAccount, "confirmed");
+    await refundTx("refund-direct");
+    const partnerUsdcAfter = await connection.getTokenAccountBalance(partnerUsdcAccount, "confirmed");
+
+    assert.equal(
+      Number(partnerUsdcAfter.value.amount) - Number(partnerUsdcBefore.value.amount),
+      usdcOut,
+      "UsdcDirect refund should pay the credited USDC to the user"
+    );
+    assert.deepEqual((await fetchReceipt("refund-direct")).status, { refunded: {} }, "Receipt should be marked refunded");
+
+    let merchantBalance = await tokenDeployerProgram.account.merchantBalance.fetch(merchant.merchantBalancePda, "confirmed");
+    assert.equal(merchantBalance.balance.toNumber(), 0, "Refund should be taken from the merchant balance");
+
+    console.log("Refunding the same purchase twice (it should fail)");
+    const secondDirectRefundFailed = await expectTxToFailWith(refundTx("refund-direct"), "PurchaseAlreadyRefunded");
+    assert(secondDirectRefundFailed, "A purchase should only be refunded once");
+
+    await tokenDeployerProgram.methods
+      .updateRefundPolicy({ reMint: {} })
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await merchant.purchase("refund-remint", swapAmount, usdcOut);
+
+    console.log("Routing the bought back collateral outside the vault (it should fail)");
+    const wrongDestinationFailed = await expectTxToFailWith(
+      refundTx("refund-remint", { routeDestination: partnerCollateralAccount }),
+      "InvalidRefundSwap"
+    );
+    assert(wrongDestinationFailed, "Refund routes should deliver the collateral to the ecosystem vault");
+
+    console.log("Buying back less collateral than the purchase burned (it should fail)");
+    const shortBuyBackFailed = await expectTxToFailWith(
+      refundTx("refund-remint", { collateralOut: swapAmount - 1 }),
+      "SlippageExceeded"
+    );
+    assert(shortBuyBackFailed, "Without an oracle the user should get back at least the uSP the purchase burned");
+
+    console.log("Re-minting while deposits are paused (it should fail)");
+    const setEcosystemPauseFlags = (pauseFlags) =>
+      tokenDeployerProgram.methods
+        .setEcosystemPauseFlags(pauseFlags)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          mint: mintKeypair.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    await setEcosystemPauseFlags(PAUSE_DEPOSIT);
+    const pausedRemintFailed = await expectTxToFailWith(refundTx("refund-remint"), "FreezeStateActive");
+    assert(pausedRemintFailed, "Re-mint refunds should respect the deposit pause flag");
+    await setEcosystemPauseFlags(0);
+
+    console.log("Re-minting above the mint rate limit (it should fail)");
+    const updateMintRateLimit = (mintWindowLimit) =>
+      tokenDeployerProgram.methods
+        .updateMintRateLimit(new anchor.BN(mintWindowLimit), new anchor.BN(60 * 60))
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+        })
+        .rpc({ commitment: "confirmed" });
+    await updateMintRateLimit(swapAmount - 1);
+    const rateLimitedRemintFailed = await expectTxToFailWith(refundTx("refund-remint"), "MintRateLimitExceeded");
+    assert(rateLimitedRemintFailed, "Re-mint refunds should count against the mint rate limit");
+    await updateMintRateLimit(0);
+
+    const partnerUspBefore = await connection.getTokenAccountBalance(ecosystemPartnerTokenAccount, "confirmed");
+    const collateralVaultBefore = await connection.getTokenAccountBalance(collateralVaultPda, "confirmed");
+    await refundTx("refund-remint");
+    const partnerUspAfter = await connection.getTokenAccountBalance(ecosystemPartnerTokenAccount, "confirmed");
+    const collateralVaultAfter = await connection.getTokenAccountBalance(collateralVaultPda, "confirmed");
+
+    assert.equal(
+      Number(partnerUspAfter.value.amount) - Number(partnerUspBefore.value.amount),
+      swapAmount,
+      "ReMint refund should re-mint the bought back collateral as uSP to the user"
+    );
+    assert.equal(
+      Number(collateralVaultAfter.value.amount) - Number(collateralVaultBefore.value.amount),
+      swapAmount,
+      "Bought back collateral should back the re-minted uSP"
+    );
+    assert.deepEqual((await fetchReceipt("refund-remint")).status, { refunded: {} }, "Receipt should be marked refunded");
+
+    merchantBalance = await tokenDeployerProgram.account.merchantBalance.fetch(merchant.merchantBalancePda, "confirmed");
+    assert.equal(merchantBalance.balance.toNumber(), 0, "Refund should be taken from the merchant balance");
+
+    console.log("Refunding the re-minted purchase again (it should fail)");
+    const secondRemintRefundFailed = await expectTxToFailWith(refundTx("refund-remint"), "PurchaseAlreadyRefunded");
+    assert(secondRemintRefundFailed, "A purchase should only be refunded once");
+  });
+
+  it("Instant settlement", async function () {
+    requireLocalnetBuild(this, "to route swaps through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+
+    const merchant = await setupMerchant();
+    const swapAmount = 10 * 10 ** decimals;
+    const usdcOut = 5 * 10 ** 6;
+
+    const manageMerchantAccounts = {
+      config: configPda,
+      payer: ecosystemPartnerKeypair.publicKey,
+      mint: mintKeypair.publicKey,
+      ecosystemConfig: ecosystemConfigPda,
+      registeredMerchant: merchant.merchantPda,
+    };
+    await tokenDeployerProgram.methods
+      .setMerchantSettlementMode(true)
+      .accounts(manageMerchantAccounts)
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const tokenBalance = async (account) =>
+      Number((await connection.getTokenAccountBalance(account, "confirmed")).value.amount);
+    const fetchReceipt = (purchaseReference) =>
+      tokenDeployerProgram.account.purchaseReceipt.fetch(merchant.purchaseReceiptPda(purchaseReference), "confirmed");
+    const fetchMerchantBalance = () =>
+      tokenDeployerProgram.account.merchantBalance.fetch(merchant.merchantBalancePda, "confirmed");
+
+    const merchantUsdcBefore = await tokenBalance(merchant.merchantUsdcAccount);
+    const settlementFeeVaultBefore = await tokenBalance(settlementFeeVaultPda);
+    await merchant.purchase("instant-1", swapAmount, usdcOut);
+    const settledAmount = (await tokenBalance(merchant.merchantUsdcAccount)) - merchantUsdcBefore;
+    const feeAmount = (await tokenBalance(settlementFeeVaultPda)) - settlementFeeVaultBefore;
+
+    assert(settledAmount > 0, "Instant settlement should pay the merchant's payout address");
+    assert.equal(settledAmount + feeAmount, usdcOut, "Settlement and withdrawal fee should add up to the swap output");
+
+    let receipt = await fetchReceipt("instant-1");
+    assert.equal(receipt.settlementCredited.toNumber(), 0, "Nothing should be credited to the merchant balance");
+    assert.equal(receipt.settlementPaidOut.toNumber(), settledAmount, "Receipt should record the settled USDC");
+    assert.equal((await fetchMerchantBalance()).balance.toNumber(), 0, "Merchant balance should stay empty");
+
+    console.log("Refunding an instantly settled purchase (it should fail)");
+    const settledRefundFailed = await expectTxToFailWith(
+      tokenDeployerProgram.methods
+        .refundPurchase("instant-1", new anchor.BN(0), Buffer.from([]))
+        .accounts({
+          payer: merchant.merchantKeypair.publicKey,
+          mint: mintKeypair.publicKey,
+          config: configPda,
+          ecosystemConfig: ecosystemConfigPda,
+          merchantBalance: merchant.merchantBalancePda,
+          purchaseReceipt: merchant.purchaseReceiptPda("instant-1"),
+          registeredMerchant: merchant.merchantPda,
+          outputMint: USDC_MINT,
+          outputMintProgram: tokenProgramFor(USDC_MINT),
+          vault: merchant.vaultPda,
+          vaultOutputTokenAccount: merchant.vaultOutputTokenAccount,
+          userOutputTokenAccount: null,
+          inputMint: null,
+          inputMintProgram: null,
+          vaultInputTokenAccount: null,
+          collateralVault: null,
+          mintAuthority: null,
+          userTokenAccount: null,
+          tokenProgram: null,
+          jupiterProgram: null,
+          priceFeed: null,
+        })
+        .signers([merchant.merchantKeypair])
+        .rpc({ commitment: "confirmed" }),
+      "SettledPurchaseNotRefundable"
+    );
+    assert(settledRefundFailed, "Settled USDC isn't in the merchant balance, so it can't be refunded from it");
+
+    // Freezing the balance holds back instant settlements, proceeds go to the frozen balance instead
+    await tokenDeployerProgram.methods
+      .setMerchantStatus(true, true)
+      .accounts(manageMerchantAccounts)
+      .signers([ecosystemPartnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    const frozenMerchantUsdcBefore = await tokenBalance(merchant.merchantUsdcAccount);
+    await merchant.purchase("instant-2", swapAmount, usdcOut);
+
+    assert.equal(
+      await tokenBalance(merchant.merchantUsdcAccount),
+      frozenMerchantUsdcBefore,
+      "A frozen merchant should not be paid out"
+    );
+    receipt = await fetchReceipt("instant-2");
+    assert.equal(receipt.settlementCredited.toNumber(), usdcOut, "Proceeds should be credited to the frozen balance");
+    assert.equal(receipt.settlementPaidOut.toNumber(), 0, "Nothing should be settled while the balance is frozen");
+    assert.equal((await fetchMerchantBalance()).balance.toNumber(), usdcOut, "Merchant balance should hold the proceeds");
+  });
+
+  it("Withdrawal approval thresholds", async () => {
+    const approvers = [Keypair.generate().publicKey, Keypair.generate().publicKey];
+    for (const approver of approvers) {
+      await tokenDeployerProgram.methods
+        .addApprover(approver)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    }
+
+    // Earlier tests may have left the wallet as an approver
+    const approverCount = (await tokenDeployerProgram.account.config.fetch(configPda, "confirmed")).approvers.length;
+
+    console.log("Setting threshold above approver count (it should fail)");
+    const invalidThresholdTx = tokenDeployerProgram.methods
+      .setApprovalThreshold(approverCount + 1)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      });
+    const invalidThresholdFailed = await expectTxToFail(invalidThresholdTx.rpc({ commitment: "confirmed" }));
+    assert(invalidThresholdFailed, "Threshold above the number of approvers should be rejected");
+
+    await tokenDeployerProgram.methods
+      .setApprovalThreshold(approverCount)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.equal(config.approvalThreshold, approverCount, "Global approval threshold should be updated");
+
+    console.log("Removing approver below threshold (it should fail)");
+    const removeBelowThresholdTx = tokenDeployerProgram.methods
+      .removeApprover(approvers[0])
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      });
+    const removeBelowThresholdFailed = await expectTxToFail(removeBelowThresholdTx.rpc({ commitment: "confirmed" }));
+    assert(removeBelowThresholdFailed, "Approvers should not drop below the approval threshold");
+
+    console.log("Setting high value threshold below base threshold (it should fail)");
+    const invalidHighValueTx = tokenDeployerProgram.methods
+      .setEcosystemApprovalThreshold(2, new anchor.BN(1000 * 10 ** decimals), 1)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      });
+    const invalidHighValueFailed = await expectTxToFail(invalidHighValueTx.rpc({ commitment: "confirmed" }));
+    assert(invalidHighValueFailed, "High value threshold should not be below the base threshold");
+
+    await tokenDeployerProgram.methods
+      .setEcosystemApprovalThreshold(1, new anchor.BN(1000 * 10 ** decimals), 2)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.approvalThreshold, 1, "Ecosystem approval threshold should be updated");
+    assert.equal(ecosystemConfig.highValueApprovalThreshold, 2, "High value approval threshold should be updated");
+
+    await tokenDeployerProgram.methods
+      .setApprovalThreshold(1)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await tokenDeployerProgram.methods
+      .removeApprover(approvers[0])
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+  });
+
+  it("Approver removal", async function () {
+    const setApprovalThreshold = (approvalThreshold) =>
+      tokenDeployerProgram.methods
+        .setApprovalThreshold(approvalThreshold)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    const addApprover = (approver) =>
+      tokenDeployerProgram.methods
+        .addApprover(approver)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+    const removeApprover = (approver) =>
+      tokenDeployerProgram.methods
+        .removeApprover(approver)
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    await ensureWalletIsApprover();
+    await setApprovalThreshold(1);
+
+    let config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    for (const approver of config.approvers.filter((x) => !x.equals(wallet.publicKey))) {
+      await removeApprover(approver);
+    }
+
+    console.log("Removing the last approver while one approval is required (it should fail)");
+    const removeLastFailed = await expectTxToFailWith(removeApprover(wallet.publicKey), "InvalidApprovalThreshold");
+    assert(removeLastFailed, "Approvers should not drop below the approval threshold");
+
+    await setApprovalThreshold(0);
+    await removeApprover(wallet.publicKey);
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.equal(config.approvers.length, 0, "Every approver should be removable with a threshold of 0");
+
+    await addApprover(wallet.publicKey);
+    await setApprovalThreshold(1);
+
+    requireLocalnetBuild(this, "to pay withdrawals through mock jupiter");
+
+    const approverKeypairs = [Keypair.generate(), Keypair.generate()];
+    for (const approverKeypair of approverKeypairs) {
+      await connection.confirmTransaction(
+        await connection.requestAirdrop(approverKeypair.publicKey, LAMPORTS_PER_SOL),
+        "confirmed"
+      );
+      await addApprover(approverKeypair.publicKey);
+    }
+
+    await tokenDeployerProgram.methods
+      .setEcosystemApprovalThreshold(3, new anchor.BN(0), 0)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    const merchant = await setupMerchant();
+    const usdcOut = 5 * 10 ** 6;
+    await merchant.purchase("approvers-1", 10 * 10 ** decimals, usdcOut);
+    const withdrawalRequestPda = await merchant.requestWithdrawal(usdcOut);
+
+    await merchant.approveWithdrawal(withdrawalRequestPda, approverKeypairs[0]);
+    let withdrawalRequest = await tokenDeployerProgram.account.withdrawalRequest.fetch(withdrawalRequestPda, "confirmed");
+    assert.equal(withdrawalRequest.approvals.length, 1, "First approval should be recorded");
+
+    for (const approverKeypair of approverKeypairs) {
+      await removeApprover(approverKeypair.publicKey);
+    }
+
+    // The ecosystem still asks for 3 approvals, capped at the single approver left. The approval
+    // from the removed approver is pruned, so the wallet's approval alone pays the request out.
+    await merchant.approveWithdrawal(withdrawalRequestPda);
+
+    withdrawalRequest = await tokenDeployerProgram.account.withdrawalRequest.fetch(withdrawalRequestPda, "confirmed");
+    assert.deepEqual(withdrawalRequest.status, { approved: {} }, "Request should be paid out by the remaining approver");
+
+    const merchantUsdc = await connection.getTokenAccountBalance(merchant.merchantUsdcAccount, "confirmed");
+    assert.equal(Number(merchantUsdc.value.amount), usdcOut - usdcOut / 5, "Merchant should receive the withdrawal minus the fee");
+  });
+
+  it("Reject and cancel withdrawal requests", async function () {
+    requireLocalnetBuild(this, "to credit merchants through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    await ensureWalletIsApprover();
+
+    const merchant = await setupMerchant();
+    const usdcOut = 5 * 10 ** 6;
+    await merchant.purchase("reject-1", 10 * 10 ** decimals, usdcOut);
+
+    const fetchMerchantBalance = () =>
+      tokenDeployerProgram.account.merchantBalance.fetch(merchant.merchantBalancePda, "confirmed");
+
+    const rejectedRequestPda = await merchant.requestWithdrawal(2 * 10 ** 6);
+    assert.equal((await fetchMerchantBalance()).reservedBalance.toNumber(), 2 * 10 ** 6, "Request should reserve its amount");
+
+    console.log("Rejecting a withdrawal request as a non approver (it should fail)");
+    const unauthorizedRejectFailed = await expectTxToFailWith(
+      merchant.rejectWithdrawal(rejectedRequestPda, 1, unauthorizedWalletKeypair),
+      "NotAnApprover"
+    );
+    assert(unauthorizedRejectFailed, "Only approvers should be able to reject withdrawal requests");
+
+    console.log("Rejecting a withdrawal request as its merchant (it should fail)");
+    const merchantRejectFailed = await expectTxToFailWith(
+      merchant.rejectWithdrawal(rejectedRequestPda, 1, merchant.merchantKeypair),
+      "NotAnApprover"
+    );
+    assert(merchantRejectFailed, "Merchants should not be able to reject their own withdrawal requests");
+
+    await merchant.rejectWithdrawal(rejectedRequestPda, 7);
+
+    const rejectedRequest = await tokenDeployerProgram.account.withdrawalRequest.fetch(rejectedRequestPda, "confirmed");
+    assert.deepEqual(rejectedRequest.status, { rejected: {} }, "Request should be rejected");
+    assert.equal(rejectedRequest.reasonCode, 7, "Reason code should be recorded");
+    assert(rejectedRequest.resolvedBy.equals(wallet.publicKey), "Rejecting approver should be recorded");
+
+    let merchantBalance = await fetchMerchantBalance();
+    assert.equal(merchantBalance.reservedBalance.toNumber(), 0, "Rejection should release the reservation");
+    assert.equal(merchantBalance.balance.toNumber(), usdcOut, "Rejection should leave the balance untouched");
+
+    console.log("Approving a rejected withdrawal request (it should fail)");
+    const approveRejectedFailed = await expectTxToFailWith(
+      merchant.approveWithdrawal(rejectedRequestPda),
+      "WithdrawalRequestNotPending"
+    );
+    assert(approveRejectedFailed, "Resolved requests should not be approvable");
+
+    console.log("Cancelling a rejected withdrawal request (it should fail)");
+    const cancelRejectedFailed = await expectTxToFailWith(
+      merchant.cancelWithdrawal(rejectedRequestPda),
+      "WithdrawalRequestNotPending"
+    );
+    assert(cancelRejectedFailed, "Resolved requests should not be cancellable");
+
+    const cancelledRequestPda = await merchant.requestWithdrawal(3 * 10 ** 6);
+    assert.equal((await fetchMerchantBalance()).reservedBalance.toNumber(), 3 * 10 ** 6, "Request should reserve its amount");
+
+    console.log("Cancelling another merchant's withdrawal request (it should fail)");
+    const unauthorizedCancelFailed = await expectTxToFail(
+      merchant.cancelWithdrawal(cancelledRequestPda, unauthorizedWalletKeypair)
+    );
+    assert(unauthorizedCancelFailed, "Only the requesting merchant should be able to cancel");
+
+    const merchantLamportsBefore = await connection.getBalance(merchant.merchantKeypair.publicKey, "confirmed");
+    await merchant.cancelWithdrawal(cancelledRequestPda);
+    const merchantLamportsAfter = await connection.getBalance(merchant.merchantKeypair.publicKey, "confirmed");
+
+    assert.isNull(
+      await connection.getAccountInfo(cancelledRequestPda, "confirmed"),
+      "Cancelled request should be closed"
+    );
+    assert(merchantLamportsAfter > merchantLamportsBefore, "Cancelling should refund the request rent to the merchant");
+
+    merchantBalance = await fetchMerchantBalance();
+    assert.equal(merchantBalance.reservedBalance.toNumber(), 0, "Cancellation should release the reservation");
+    assert.equal(merchantBalance.balance.toNumber(), usdcOut, "Cancellation should leave the balance untouched");
+
+    console.log("Approving a cancelled withdrawal request (it should fail)");
+    const approveCancelledFailed = await expectTxToFail(merchant.approveWithdrawal(cancelledRequestPda));
+    assert(approveCancelledFailed, "Cancelled requests should not be approvable");
+  });
+
+  it("Partial withdrawals and merchant balance migration", async function () {
+    requireLocalnetBuild(this, "to credit merchants through mock jupiter");
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    await ensureWalletIsApprover();
+
+    const merchant = await setupMerchant();
+    const usdcOut = 5 * 10 ** 6;
+    await merchant.purchase("partial-1", 10 * 10 ** decimals, usdcOut);
+
+    const fetchMerchantBalance = () =>
+      tokenDeployerProgram.account.merchantBalance.fetch(merchant.merchantBalancePda, "confirmed");
+
+    let merchantBalance = await fetchMerchantBalance();
+    assert(merchantBalance.version > 0, "New merchant balances should be created at the current layout version");
+
+    const firstRequestPda = await merchant.requestWithdrawal(2 * 10 ** 6);
+
+    console.log("Requesting more than the unreserved balance (it should fail)");
+    const overReservedFailed = await expectTxToFailWith(merchant.requestWithdrawal(4 * 10 ** 6), "InsufficientBalance");
+    assert(overReservedFailed, "Pending requests should reserve their amount");
+
+    const secondRequestPda = await merchant.requestWithdrawal(3 * 10 ** 6);
+
+    merchantBalance = await fetchMerchantBalance();
+    assert.equal(merchantBalance.reservedBalance.toNumber(), usdcOut, "Both requests should be reserved");
+    assert.equal(merchantBalance.requestCount.toNumber(), 2, "Each request should take the next index");
+
+    console.log("Requesting with the whole balance reserved (it should fail)");
+    const fullyReservedFailed = await expectTxToFailWith(merchant.requestWithdrawal(1), "NoBalanceToWithdraw");
+    assert(fullyReservedFailed, "Nothing should be withdrawable while the whole balance is reserved");
+
+    await merchant.approveWithdrawal(firstRequestPda);
+
+    merchantBalance = await fetchMerchantBalance();
+    assert.equal(merchantBalance.balance.toNumber(), 3 * 10 ** 6, "Approved amount should leave the balance");
+    assert.equal(merchantBalance.reservedBalance.toNumber(), 3 * 10 ** 6, "Only the pending request should stay reserved");
+
+    let ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.totalMerchantBalance.toNumber(), 3 * 10 ** 6, "Approved amount should no longer be owed");
+
+    await merchant.approveWithdrawal(secondRequestPda);
+
+    merchantBalance = await fetchMerchantBalance();
+    assert.equal(merchantBalance.balance.toNumber(), 0, "Both partial withdrawals should drain the balance");
+    assert.equal(merchantBalance.reservedBalance.toNumber(), 0, "No reservation should be left");
+
+    const merchantUsdc = await connection.getTokenAccountBalance(merchant.merchantUsdcAccount, "confirmed");
+    assert.equal(Number(merchantUsdc.value.amount), usdcOut - usdcOut / 5, "Merchant should receive both withdrawals minus fees");
+
+    const migrateMerchantBalanceTx = (payer) =>
+      tokenDeployerProgram.methods
+        .migrateMerchantBalance()
+        .accounts({
+          config: configPda,
+          payer: payer.publicKey,
+          mint: mintKeypair.publicKey,
+          merchantWallet: merchant.merchantKeypair.publicKey,
+          merchantBalance: merchant.merchantBalancePda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Migrating a merchant balance with unauthorized wallet (it should fail)");
+    const unauthorizedMigrateFailed = await expectTxToFailWith(
+      migrateMerchantBalanceTx(unauthorizedWalletKeypair),
+      "Unauthorized"
+    );
+    assert(unauthorizedMigrateFailed, "Only owner or the merchant should be able to migrate a merchant balance");
+
+    console.log("Migrating an up to date merchant balance (it should fail)");
+    const upToDateMigrateFailed = await expectTxToFailWith(
+      migrateMerchantBalanceTx(merchant.merchantKeypair),
+      "MerchantBalanceUpToDate"
+    );
+    assert(upToDateMigrateFailed, "Merchant balances at the current version should not be migrated again");
+
+    // Only un-indexed requests from before partial withdrawals can be closed this way
+    const [legacyWithdrawalRequestPda] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("withdrawal_request"),
+        merchant.merchantKeypair.publicKey.toBuffer(),
+        ecosystemConfigPda.toBuffer(),
+      ],
+      tokenDeployerProgram.programId
+    );
+
+    console.log("Closing a legacy withdrawal request the merchant never had (it should fail)");
+    const noLegacyRequestFailed = await expectTxToFailWith(
+      tokenDeployerProgram.methods
+        .closeLegacyWithdrawalRequest()
+        .accounts({
+          config: configPda,
+          payer: merchant.merchantKeypair.publicKey,
+          merchant: merchant.merchantKeypair.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          legacyWithdrawalRequest: legacyWithdrawalRequestPda,
+        })
+        .signers([merchant.merchantKeypair])
+        .rpc({ commitment: "confirmed" }),
+      "InvalidProgramId"
+    );
+    assert(noLegacyRequestFailed, "Only existing legacy withdrawal requests should be closable");
+  });
+
+  it("Resolved withdrawal requests shrink to receipts", async function () {
+    requireLocalnetBuild(this, "to credit merchants through mock jupiter");
+
+    // Matches WITHDRAWAL_RECEIPT_SPACE, the request without room for approvals
+    const receiptSpace = 8 + 32 + 32 + 8 + 8 + 1 + 33 + 8 + 8 + 2 + 4;
+
+    await mintTokensWithPartner(100 * 10 ** decimals);
+    await ensureWalletIsApprover();
+
+    const merchant = await setupMerchant();
+    await merchant.purchase("receipt-1", 10 * 10 ** decimals, 5 * 10 ** 6);
+
+    const merchantWallet = merchant.merchantKeypair.publicKey;
+    const receiptRent = await connection.getMinimumBalanceForRentExemption(receiptSpace);
+
+    for (const resolve of [
+      (withdrawalRequestPda) => merchant.approveWithdrawal(withdrawalRequestPda),
+      (withdrawalRequestPda) => merchant.rejectWithdrawal(withdrawalRequestPda, 3),
+    ]) {
+      const withdrawalRequestPda = await merchant.requestWithdrawal(10 ** 6);
+      const requestInfo = await connection.getAccountInfo(withdrawalRequestPda, "confirmed");
+      assert(requestInfo.data.length > receiptSpace, "Pending requests should have room for approvals");
+
+      const merchantLamportsBefore = await connection.getBalance(merchantWallet, "confirmed");
+      await resolve(withdrawalRequestPda);
+      const merchantLamportsAfter = await connection.getBalance(merchantWallet, "confirmed");
+
+      const receiptInfo = await connection.getAccountInfo(withdrawalRequestPda, "confirmed");
+      assert.equal(receiptInfo.data.length, receiptSpace, "Resolved request should shrink to the receipt size");
+      assert.equal(receiptInfo.lamports, receiptRent, "Receipt should only keep its own rent");
+      assert.equal(
+        merchantLamportsAfter - merchantLamportsBefore,
+        requestInfo.lamports - receiptRent,
+        "Freed rent should be refunded to the merchant"
+      );
+
+      const receipt = await tokenDeployerProgram.account.withdrawalRequest.fetch(withdrawalRequestPda, "confirmed");
+      assert.equal(receipt.amount.toNumber(), 10 ** 6, "Receipt should still deserialize with its amount");
+      assert.deepEqual(receipt.approvals, [], "Receipt should not keep approvals");
+      assert(receipt.resolvedBy.equals(wallet.publicKey), "Receipt should record who resolved it");
+    }
+  });
+
+  it("Role based access control", async () => {
+    const guardianKeypair = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(guardianKeypair.publicKey, LAMPORTS_PER_SOL)
+    );
+
+    console.log("Non owner granting a role (it should fail)");
+    const unauthorizedGrantTx = tokenDeployerProgram.methods
+      .grantRole(unauthorizedWalletKeypair.publicKey, { freezeGuardian: {} })
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedGrantFailed = await expectTxToFail(unauthorizedGrantTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedGrantFailed, "Only the owner should be able to grant roles");
+
+    console.log("Freezing without the freeze guardian role (it should fail)");
+    const ungrantedFreezeTx = tokenDeployerProgram.methods
+      .setGlobalPauseFlags(PAUSE_DEPOSIT)
+      .accounts({
+        config: configPda,
+        payer: guardianKeypair.publicKey,
+      })
+      .signers([guardianKeypair]);
+    const ungrantedFreezeFailed = await expectTxToFail(ungrantedFreezeTx.rpc({ commitment: "confirmed" }));
+    assert(ungrantedFreezeFailed, "Wallets without the freeze guardian role should not be able to freeze");
+
+    await tokenDeployerProgram.methods
+      .grantRole(guardianKeypair.publicKey, { freezeGuardian: {} })
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    let config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    const roleMember = config.roleMembers.find((x) => x.member.equals(guardianKeypair.publicKey));
+    assert(roleMember, "Freeze guardian should be stored in config");
+
+    for (const pauseFlags of [PAUSE_DEPOSIT, 0]) {
+      await tokenDeployerProgram.methods
+        .setGlobalPauseFlags(pauseFlags)
+        .accounts({
+          config: configPda,
+          payer: guardianKeypair.publicKey,
+        })
+        .signers([guardianKeypair])
+        .rpc({ commitment: "confirmed" });
+    }
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.equal(config.pauseFlags, 0, "Global pause should be lifted again");
+
+    console.log("Freeze guardian updating the max cap (it should fail)");
+    const wrongRoleTx = tokenDeployerProgram.methods
+      .updateMaxCap(new anchor.BN(1))
+      .accounts({
+        config: configPda,
+        payer: guardianKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+      })
+      .signers([guardianKeypair]);
+    const wrongRoleFailed = await expectTxToFail(wrongRoleTx.rpc({ commitment: "confirmed" }));
+    assert(wrongRoleFailed, "Freeze guardian should not be able to update the max cap");
+
+    await tokenDeployerProgram.methods
+      .revokeRole(guardianKeypair.publicKey, { freezeGuardian: {} })
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.isUndefined(
+      config.roleMembers.find((x) => x.member.equals(guardianKeypair.publicKey)),
+      "Role member should be removed once it holds no roles"
+    );
+
+    const revokedFreezeTx = tokenDeployerProgram.methods
+      .setGlobalPauseFlags(PAUSE_DEPOSIT)
+      .accounts({
+        config: configPda,
+        payer: guardianKeypair.publicKey,
+      })
+      .signers([guardianKeypair]);
+    const revokedFreezeFailed = await expectTxToFail(revokedFreezeTx.rpc({ commitment: "confirmed" }));
+    assert(revokedFreezeFailed, "Revoked freeze guardian should not be able to freeze");
+  });
+
+  it("Settlement mint allow-list", async () => {
+    const settlementMint = Keypair.generate().publicKey;
+
+    console.log("Non owner adding a settlement mint (it should fail)");
+    const unauthorizedAddTx = tokenDeployerProgram.methods
+      .addSettlementMint(settlementMint)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedAddFailed = await expectTxToFail(unauthorizedAddTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedAddFailed, "Non owner should not be able to add a settlement mint");
+
+    await tokenDeployerProgram.methods
+      .addSettlementMint(settlementMint)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    let config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert(config.allowedSettlementMints.some((x) => x.equals(USDC_MINT)), "USDC should be allowed by default");
+    assert(config.allowedSettlementMints.some((x) => x.equals(settlementMint)), "Settlement mint should be allowed");
+
+    console.log("Adding the same settlement mint twice (it should fail)");
+    const duplicateAddTx = tokenDeployerProgram.methods
+      .addSettlementMint(settlementMint)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      });
+    const duplicateAddFailed = await expectTxToFail(duplicateAddTx.rpc({ commitment: "confirmed" }));
+    assert(duplicateAddFailed, "Settlement mint should not be added twice");
+
+    await tokenDeployerProgram.methods
+      .removeSettlementMint(settlementMint)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert(!config.allowedSettlementMints.some((x) => x.equals(settlementMint)), "Settlement mint should be removed");
+
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert(ecosystemConfig.settlementMint.equals(USDC_MINT), "Ecosystem should settle in USDC");
+  });
+
+  it("Two step ownership transfer", async () => {
+    const newOwnerKeypair = Keypair.generate();
+    await connection.confirmTransaction(
+      await connection.requestAirdrop(newOwnerKeypair.publicKey, LAMPORTS_PER_SOL)
+    );
+
+    console.log("Non owner proposing new owner (it should fail)");
+    const unauthorizedProposeTx = tokenDeployerProgram.methods
+      .proposeOwner(unauthorizedWalletKeypair.publicKey)
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedProposeFailed = await expectTxToFail(unauthorizedProposeTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedProposeFailed, "Non owner should not be able to propose a new owner");
+
+    await tokenDeployerProgram.methods
+      .proposeOwner(newOwnerKeypair.publicKey)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    let config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert(config.pendingOwner.equals(newOwnerKeypair.publicKey), "Pending owner should be set");
+    assert(config.owner.equals(wallet.publicKey), "Owner should not change before acceptance");
+
+    console.log("Accepting ownership with wrong wallet (it should fail)");
+    const wrongAcceptTx = tokenDeployerProgram.methods
+      .acceptOwner()
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const wrongAcceptFailed = await expectTxToFail(wrongAcceptTx.rpc({ commitment: "confirmed" }));
+    assert(wrongAcceptFailed, "Only the pending owner should be able to accept ownership");
+
+    await tokenDeployerProgram.methods
+      .cancelOwnerTransfer()
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.isNull(config.pendingOwner, "Pending owner should be cleared after cancel");
+
+    const cancelledAcceptTx = tokenDeployerProgram.methods
+      .acceptOwner()
+      .accounts({
+        config: configPda,
+        payer: newOwnerKeypair.publicKey,
+      })
+      .signers([newOwnerKeypair]);
+    const cancelledAcceptFailed = await expectTxToFail(cancelledAcceptTx.rpc({ commitment: "confirmed" }));
+    assert(cancelledAcceptFailed, "Cancelled transfer should not be accepted");
+
+    await tokenDeployerProgram.methods
+      .proposeOwner(newOwnerKeypair.publicKey)
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await tokenDeployerProgram.methods
+      .acceptOwner()
+      .accounts({
+        config: configPda,
+        payer: newOwnerKeypair.publicKey,
+      })
+      .signers([newOwnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert(config.owner.equals(newOwnerKeypair.publicKey), "New owner should be set after acceptance");
+
+    console.log("Handing ownership back to the original wallet");
+    await tokenDeployerProgram.methods
+      .proposeOwner(wallet.publicKey)
+      .accounts({
+        config: configPda,
+        payer: newOwnerKeypair.publicKey,
+      })
+      .signers([newOwnerKeypair])
+      .rpc({ commitment: "confirmed" });
+
+    await tokenDeployerProgram.methods
+      .acceptOwner()
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert(config.owner.equals(wallet.publicKey), "Ownership should be restored to the original wallet");
+  });
+
+  it("Config migration", async () => {
+    const config = await tokenDeployerProgram.account.config.fetch(configPda, "confirmed");
+    assert.isAbove(config.version, 0, "Initialized configs should be at the current version");
+    assert(
+      config.allowedSettlementMints.some((x) => x.equals(USDC_MINT)),
+      "USDC should be an allowed settlement mint"
+    );
+
+    console.log("Migrating config with unauthorized wallet (it should fail)");
+    const unauthorizedMigrateFailed = await expectTxToFailWith(
+      tokenDeployerProgram.methods
+        .migrateConfig()
+        .accounts({
+          config: configPda,
+          payer: unauthorizedWalletKeypair.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([unauthorizedWalletKeypair])
+        .rpc({ commitment: "confirmed" }),
+      "Unauthorized"
+    );
+    assert(unauthorizedMigrateFailed, "Only owner should be able to migrate the config");
+
+    console.log("Migrating an up to date config (it should fail)");
+    const upToDateMigrateFailed = await expectTxToFailWith(
+      tokenDeployerProgram.methods
+        .migrateConfig()
+        .accounts({
+          config: configPda,
+          payer: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ commitment: "confirmed" }),
+      "ConfigUpToDate"
+    );
+    assert(upToDateMigrateFailed, "Configs at the current version should not be migrated again");
+  });
+
+  it("Ecosystem config migration", async () => {
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert(ecosystemConfig.version > 0, "New ecosystems should be created at the current layout version");
+
+    console.log("Migrating ecosystem config with unauthorized wallet (it should fail)");
+    const unauthorizedMigrateTx = tokenDeployerProgram.methods
+      .migrateEcosystemConfig()
+      .accounts({
+        config: configPda,
+        payer: unauthorizedWalletKeypair.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([unauthorizedWalletKeypair]);
+    const unauthorizedMigrateFailed = await expectTxToFail(unauthorizedMigrateTx.rpc({ commitment: "confirmed" }));
+    assert(unauthorizedMigrateFailed, "Only owner should be able to migrate an ecosystem config");
+
+    console.log("Migrating an up to date ecosystem config (it should fail)");
+    const upToDateMigrateTx = tokenDeployerProgram.methods
+      .migrateEcosystemConfig()
+      .accounts({
+        config: configPda,
+        payer: wallet.publicKey,
+        mint: mintKeypair.publicKey,
+        ecosystemConfig: ecosystemConfigPda,
+        systemProgram: SystemProgram.programId,
+      });
+    const upToDateMigrateFailed = await expectTxToFail(upToDateMigrateTx.rpc({ commitment: "confirmed" }));
+    assert(upToDateMigrateFailed, "Ecosystem configs at the current version should not be migrated again");
+  });
+
+  it("Legacy vault migration", async function () {
+    requireLocalnetBuild(this, "to use the USDC fixture");
+
+    const [legacyVaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault")],
+      tokenDeployerProgram.programId
+    );
+    const [vaultPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), mintKeypair.publicKey.toBuffer()],
+      tokenDeployerProgram.programId
+    );
+    const ata = (mint, owner) =>
+      getAssociatedTokenAddressSync(mint, owner, true, tokenProgramFor(mint), ASSOCIATED_TOKEN_PROGRAM_ID);
+
+    const legacyVaultTokenAccount = ata(USDC_MINT, legacyVaultPda);
+    const legacyVaultInputTokenAccount = ata(collateralMintKeypair.publicKey, legacyVaultPda);
+    const vaultOutputTokenAccount = ata(USDC_MINT, vaultPda);
+    const vaultInputTokenAccount = ata(collateralMintKeypair.publicKey, vaultPda);
+
+    // The legacy vault is shared by every test ecosystem, so its accounts may already exist
+    const createAtasTx = new Transaction();
+    for (const [account, owner, mint] of [
+      [legacyVaultTokenAccount, legacyVaultPda, USDC_MINT],
+      [legacyVaultInputTokenAccount, legacyVaultPda, collateralMintKeypair.publicKey],
+      [vaultInputTokenAccount, vaultPda, collateralMintKeypair.publicKey],
+    ]) {
+      if (!(await connection.getAccountInfo(account, "confirmed"))) {
+        createAtasTx.add(
+          createAssociatedTokenAccountInstruction(
+            wallet.publicKey,
+            account,
+            owner,
+            mint,
+            tokenProgramFor(mint),
+            ASSOCIATED_TOKEN_PROGRAM_ID
+          )
+        );
+      }
+    }
+    if (createAtasTx.instructions.length > 0) {
+      await sendAndConfirmTransaction(connection, createAtasTx, [wallet.payer], { commitment: "confirmed" });
+    }
+
+    const usdcAmount = 5 * 10 ** 6;
+    const collateralAmount = 2 * 10 ** collateralDecimal;
+    await mintWithMockJupiter(USDC_MINT, legacyVaultTokenAccount, usdcAmount);
+    await mintWithMockJupiter(collateralMintKeypair.publicKey, legacyVaultInputTokenAccount, collateralAmount);
+
+    const migrateTx = (amount, inputAmount, { payer = wallet.payer, outputMint = USDC_MINT, inputAccounts = true } = {}) =>
+      tokenDeployerProgram.methods
+        .migrateLegacyVault(new anchor.BN(amount), new anchor.BN(inputAmount))
+        .accounts({
+          config: configPda,
+          payer: payer.publicKey,
+          mint: mintKeypair.publicKey,
+          ecosystemConfig: ecosystemConfigPda,
+          outputMint,
+          outputMintProgram: tokenProgramFor(outputMint),
+          legacyVault: legacyVaultPda,
+          legacyVaultTokenAccount: ata(outputMint, legacyVaultPda),
+          vault: vaultPda,
+          vaultOutputTokenAccount: ata(outputMint, vaultPda),
+          inputMint: inputAccounts ? collateralMintKeypair.publicKey : null,
+          inputMintProgram: inputAccounts ? TOKEN_2022_PROGRAM_ID : null,
+          legacyVaultInputTokenAccount: inputAccounts ? legacyVaultInputTokenAccount : null,
+          vaultInputTokenAccount: inputAccounts ? vaultInputTokenAccount : null,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([payer])
+        .rpc({ commitment: "confirmed" });
+
+    console.log("Migrating legacy vault with unauthorized wallet (it should fail)");
+    const unauthorizedMigrateFailed = await expectTxToFail(
+      migrateTx(usdcAmount, 0, { payer: unauthorizedWalletKeypair })
+    );
+    assert(unauthorizedMigrateFailed, "Only owner should be able to migrate the legacy vault");
+
+    console.log("Migrating legacy vault in a mint other than the settlement mint (it should fail)");
+    const wrongMintFailed = await expectTxToFail(
+      migrateTx(collateralAmount, 0, { outputMint: collateralMintKeypair.publicKey })
+    );
+    assert(wrongMintFailed, "Only the ecosystem settlement mint should be migrated as proceeds");
+
+    console.log("Migrating collateral without the collateral accounts (it should fail)");
+    const missingAccountsFailed = await expectTxToFail(migrateTx(0, collateralAmount, { inputAccounts: false }));
+    assert(missingAccountsFailed, "Moving collateral should require the collateral token accounts");
+
+    await migrateTx(usdcAmount, collateralAmount);
+
+    const vaultOutputBalance = await connection.getTokenAccountBalance(vaultOutputTokenAccount, "confirmed");
+    assert.equal(Number(vaultOutputBalance.value.amount), usdcAmount, "USDC should move into the ecosystem vault");
+    const vaultInputBalance = await connection.getTokenAccountBalance(vaultInputTokenAccount, "confirmed");
+    assert.equal(Number(vaultInputBalance.value.amount), collateralAmount, "Collateral should move into the ecosystem vault");
+
+    const ecosystemConfig = await tokenDeployerProgram.account.ecosystemConfig.fetch(ecosystemConfigPda, "confirmed");
+    assert.equal(ecosystemConfig.legacyMigratedAmount.toNumber(), usdcAmount, "Migrated USDC should be recorded");
+    assert.equal(ecosystemConfig.legacyMigratedInputAmount.toNumber(), collateralAmount, "Migrated collateral should be recorded");
+    assert.equal(ecosystemConfig.totalMerchantBalance.toNumber(), usdcAmount, "Migrated USDC should count as owed to merchants");
   });
 });
\ No newline at end of file